@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Parser, Subcommand};
+use templus::{
+    compiler::{formatter, lexer::Syntax, parser::Parser as TemplateParser},
+    renderer::Environment,
+};
+
+#[derive(Parser)]
+#[command(
+    name = "templus",
+    version,
+    about = "Check, render and inspect templus templates"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse and validate every template in a directory
+    Check { dir: PathBuf },
+    /// Render a template with a json or yaml context
+    Render {
+        name: String,
+        /// json or yaml file with the context, picked by extension
+        #[arg(long)]
+        data: Option<PathBuf>,
+        /// directory the templates are loaded from
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Print the parse tree of a template file
+    Ast { file: PathBuf },
+    /// Normalize directive spacing and indentation of templates in place
+    Fmt {
+        path: PathBuf,
+        /// only report files that are not formatted, exits non-zero if there are any
+        #[arg(long)]
+        check: bool,
+        /// indent with this many spaces instead of tabs
+        #[arg(long)]
+        spaces: Option<usize>,
+    },
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Check { dir } => check(&dir).map(|errors| match errors {
+            0 => ExitCode::SUCCESS,
+            _ => ExitCode::FAILURE,
+        }),
+        Command::Render { name, data, dir } => render(&name, data.as_deref(), &dir),
+        Command::Ast { file } => ast(&file),
+        Command::Fmt {
+            path,
+            check,
+            spaces,
+        } => fmt(&path, check, spaces),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        ExitCode::FAILURE
+    })
+}
+
+/// reports every error in the templates below `dir`, returns how many there were
+fn check(dir: &Path) -> Result<usize, Box<dyn Error>> {
+    let sources = read_sources(dir)?;
+    let mut env = Environment::new();
+    let mut origin = HashMap::new();
+    let mut failed = 0;
+
+    for (path, source) in &sources {
+        let errors = env.check(source);
+        if !errors.is_empty() {
+            failed += errors.len();
+            for err in errors {
+                eprintln!("{}: {}", path.display(), err);
+            }
+            continue;
+        }
+        let mut file_env = Environment::new();
+        file_env.parse(source)?;
+        for (name, template) in file_env.templates.drain() {
+            origin.insert(name.clone(), path);
+            env.templates.insert(name, template);
+        }
+    }
+
+    // extends and imports can only be resolved once every file is parsed
+    let mut names = origin.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        for err in env.validate(name) {
+            failed += 1;
+            eprintln!("{}: {}", origin[name].display(), err);
+        }
+    }
+
+    match failed {
+        0 => println!("{} templates ok", origin.len()),
+        _ => eprintln!("{} errors", failed),
+    }
+    Ok(failed)
+}
+
+fn render(name: &str, data: Option<&Path>, dir: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let sources = read_sources(dir)?;
+    let mut env = Environment::new();
+    for (path, source) in &sources {
+        env.parse(source)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    let ctx = read_context(data)?;
+    print!("{}", env.render(name, &ctx)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn ast(file: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let source = fs::read_to_string(file)?;
+    let statements = TemplateParser::new(source.as_bytes())
+        .parse()
+        .map_err(|err| format!("{}: {}", file.display(), err))?;
+    for statement in statements {
+        println!("{}", statement.to_string().trim_end());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn fmt(path: &Path, check: bool, spaces: Option<usize>) -> Result<ExitCode, Box<dyn Error>> {
+    let indent = spaces.map_or("\t".to_owned(), |n| " ".repeat(n));
+    let mut unformatted = 0;
+    for (file, source) in read_sources(path)? {
+        let formatted = formatter::format(&source, &Syntax::default(), &indent)
+            .map_err(|err| format!("{}: {}", file.display(), err))?;
+        if formatted == source {
+            continue;
+        }
+        unformatted += 1;
+        match check {
+            true => println!("{}", file.display()),
+            false => fs::write(&file, formatted)?,
+        }
+    }
+    match check && unformatted > 0 {
+        true => Ok(ExitCode::FAILURE),
+        false => Ok(ExitCode::SUCCESS),
+    }
+}
+
+/// reads a single template file or every `.html` file below a directory, sorted by path
+fn read_sources(path: &Path) -> Result<Vec<(PathBuf, String)>, Box<dyn Error>> {
+    let mut files = vec![];
+    match path.is_dir() {
+        true => collect_templates(path, &mut files)?,
+        false => files.push(path.to_path_buf()),
+    }
+    files.sort();
+    let mut sources = vec![];
+    for file in files {
+        let source =
+            fs::read_to_string(&file).map_err(|err| format!("{}: {}", file.display(), err))?;
+        sources.push((file, source));
+    }
+    Ok(sources)
+}
+
+fn collect_templates(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_templates(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "html") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_context(data: Option<&Path>) -> Result<serde_json::Value, Box<dyn Error>> {
+    let Some(path) = data else {
+        return Ok(serde_json::Value::Object(Default::default()));
+    };
+    let data = fs::read_to_string(path)?;
+    let ctx = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&data)?,
+        _ => serde_json::from_str(&data)?,
+    };
+    Ok(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("templus-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn check_fixtures() {
+        assert_eq!(check(Path::new("../templus")).unwrap(), 0);
+    }
+
+    #[test]
+    fn check_reports_errors() {
+        let dir = temp_dir("check");
+        fs::create_dir(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.html"), "{{ define 'a' }}{{ if .x }}{{ end }}").unwrap();
+        fs::write(
+            dir.join("nested/b.html"),
+            "{{ define 'b' extends 'base' }}{{ import 'a' }}{{ end }}",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "{{ not a template").unwrap();
+        assert_eq!(check(&dir).unwrap(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fmt_in_place() {
+        let dir = temp_dir("fmt");
+        let file = dir.join("a.html");
+        fs::write(&file, "{{define 'a'}}\n{{.a}}\n{{end}}\n").unwrap();
+        assert_eq!(fmt(&dir, true, None).unwrap(), ExitCode::FAILURE);
+        assert_eq!(fmt(&dir, false, Some(2)).unwrap(), ExitCode::SUCCESS);
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "{{ define 'a' }}\n  {{ .a }}\n{{ end }}\n"
+        );
+        assert_eq!(fmt(&dir, true, Some(2)).unwrap(), ExitCode::SUCCESS);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn context_formats() {
+        let dir = temp_dir("context");
+        fs::write(dir.join("ctx.json"), r#"{ "user": { "name": "lommix" } }"#).unwrap();
+        fs::write(dir.join("ctx.yaml"), "user:\n  name: lommix\n").unwrap();
+        let json = read_context(Some(&dir.join("ctx.json"))).unwrap();
+        let yaml = read_context(Some(&dir.join("ctx.yaml"))).unwrap();
+        assert_eq!(json, yaml);
+        assert_eq!(read_context(None).unwrap(), serde_json::json!({}));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,449 @@
+//! Compiles templus templates into Rust code at build time.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use proc_macro2::{Ident, Literal, Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    LitStr, Token, Type,
+};
+use templus::{
+    compiler::parser::{ArithOp, Expression, IfExpr, Op, Statement},
+    renderer::Environment,
+};
+
+/// Parses every `.html` template below a directory, relative to the crate root, at compile
+/// time and implements `templus::typed::Template` for the given context structs.
+///
+/// ```ignore
+/// templus_macros::include_templates!("templates/", "index" => Index, "user" => User);
+/// ```
+///
+/// Template errors, missing templates and vars missing on the context struct are
+/// compile errors.
+#[proc_macro]
+pub fn include_templates(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as Input);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Input {
+    dir: LitStr,
+    bindings: Vec<(LitStr, Type)>,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let dir = input.parse()?;
+        let mut bindings = vec![];
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let name = input.parse()?;
+            input.parse::<Token![=>]>()?;
+            bindings.push((name, input.parse()?));
+        }
+        Ok(Self { dir, bindings })
+    }
+}
+
+fn expand(input: Input) -> syn::Result<TokenStream> {
+    let root = std::env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    let dir = root.join(input.dir.value());
+    let error = |msg: String| syn::Error::new(input.dir.span(), msg);
+
+    let mut files = vec![];
+    collect_templates(&dir, &mut files)
+        .map_err(|err| error(format!("{}: {}", dir.display(), err)))?;
+    files.sort();
+    let mut sources = vec![];
+    for file in &files {
+        let source = fs::read_to_string(file)
+            .map_err(|err| error(format!("{}: {}", file.display(), err)))?;
+        sources.push(source);
+    }
+
+    // report every broken template at once
+    let mut env = Environment::new();
+    let errors = files
+        .iter()
+        .zip(&sources)
+        .flat_map(|(file, source)| {
+            env.check(source)
+                .into_iter()
+                .map(move |err| error(format!("{}: {}", file.display(), err)))
+        })
+        .reduce(|mut all, err| {
+            all.combine(err);
+            all
+        });
+    if let Some(errors) = errors {
+        return Err(errors);
+    }
+    for (file, source) in files.iter().zip(&sources) {
+        env.parse(source)
+            .map_err(|err| error(format!("{}: {}", file.display(), err)))?;
+    }
+
+    let codegen = Codegen {
+        templates: &env.templates,
+    };
+    let mut impls = vec![];
+    for (name, ty) in &input.bindings {
+        let body = codegen
+            .template(&name.value(), &quote!(self), &mut vec![])
+            .map_err(|msg| syn::Error::new(name.span(), msg))?;
+        impls.push(quote! {
+            impl ::templus::typed::Template for #ty {
+                fn render_into(
+                    &self,
+                    out: &mut ::std::string::String,
+                ) -> ::std::result::Result<(), ::templus::compiler::error::TemplusError> {
+                    #body
+                    ::std::result::Result::Ok(())
+                }
+            }
+        });
+    }
+
+    // including the files makes cargo rebuild when a template changes
+    let paths = files.iter().map(|file| file.to_string_lossy().into_owned());
+    Ok(quote! {
+        #(const _: &str = include_str!(#paths);)*
+        #(#impls)*
+    })
+}
+
+fn collect_templates(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_templates(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "html") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+type Overrides<'t, 'a> = HashMap<&'a str, &'t [Statement<'a>]>;
+
+/// turns statements into code appending to `out`, mirroring the json renderer
+struct Codegen<'t, 'a> {
+    templates: &'t HashMap<String, Statement<'a>>,
+}
+
+impl<'t, 'a> Codegen<'t, 'a> {
+    /// `visiting` holds the templates being expanded, to catch import and extends cycles
+    fn template(
+        &self,
+        name: &str,
+        ctx: &TokenStream,
+        visiting: &mut Vec<String>,
+    ) -> Result<TokenStream, String> {
+        if visiting.iter().any(|n| n == name) {
+            return Err(format!("template '{}' includes itself", name));
+        }
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| format!("template '{}' not found", name))?;
+        visiting.push(name.to_owned());
+        let code = self.stmt(template, ctx, None, 0, visiting)?;
+        visiting.pop();
+        Ok(code)
+    }
+
+    fn stmts(
+        &self,
+        stmts: &'t [Statement<'a>],
+        ctx: &TokenStream,
+        overrides: Option<&Overrides<'t, 'a>>,
+        depth: usize,
+        visiting: &mut Vec<String>,
+    ) -> Result<TokenStream, String> {
+        let mut code = TokenStream::new();
+        for stmt in stmts {
+            code.extend(self.stmt(stmt, ctx, overrides, depth, visiting)?);
+        }
+        Ok(code)
+    }
+
+    fn stmt(
+        &self,
+        stmt: &'t Statement<'a>,
+        ctx: &TokenStream,
+        overrides: Option<&Overrides<'t, 'a>>,
+        depth: usize,
+        visiting: &mut Vec<String>,
+    ) -> Result<TokenStream, String> {
+        match stmt {
+            Statement::Expression(expr) => self.expr(expr, ctx, overrides, depth, visiting),
+            Statement::Define(_, Some(base), stmts, _) => {
+                let mut blocks = stmts
+                    .iter()
+                    .filter_map(|stmt| match stmt {
+                        Statement::Block(name, body, _) => Some((*name, body.as_slice())),
+                        _ => None,
+                    })
+                    .collect::<Overrides>();
+                // blocks of templates further down the chain win
+                blocks.extend(overrides.into_iter().flatten().map(|(k, v)| (*k, *v)));
+                let base_tmpl = self
+                    .templates
+                    .get(*base)
+                    .ok_or_else(|| format!("base template '{}' not found", base))?;
+                if visiting.iter().any(|n| n == base) {
+                    return Err(format!("template '{}' includes itself", base));
+                }
+                visiting.push(base.to_string());
+                let code = self.stmt(base_tmpl, ctx, Some(&blocks), depth, visiting)?;
+                visiting.pop();
+                Ok(code)
+            }
+            Statement::Define(_, None, stmts, _) => {
+                self.stmts(stmts, ctx, overrides, depth, visiting)
+            }
+            Statement::Block(name, stmts, _) => {
+                let body = overrides
+                    .and_then(|blocks| blocks.get(name).copied())
+                    .unwrap_or(stmts);
+                self.stmts(body, ctx, overrides, depth, visiting)
+            }
+            Statement::Import(name, _) => self.template(name, ctx, visiting),
+        }
+    }
+
+    fn expr(
+        &self,
+        expr: &'t Expression<'a>,
+        ctx: &TokenStream,
+        overrides: Option<&Overrides<'t, 'a>>,
+        depth: usize,
+        visiting: &mut Vec<String>,
+    ) -> Result<TokenStream, String> {
+        match expr {
+            Expression::Literal(text, _) | Expression::Number(text, _) => {
+                Ok(quote! { out.push_str(#text); })
+            }
+            Expression::Variable(..) | Expression::Arithmetic(..) | Expression::Negate(..) => {
+                let value = value(expr, ctx)?;
+                Ok(quote! {
+                    ::templus::typed::Output::write_output(&#value, out, true);
+                })
+            }
+            Expression::Safe(inner, _) => {
+                let value = value(inner, ctx)?;
+                Ok(quote! {
+                    ::templus::typed::Output::write_output(&#value, out, false);
+                })
+            }
+            Expression::If(ifexpr, btrue, bfalse) => {
+                let cond = condition(ifexpr, ctx)?;
+                let btrue = self.stmts(btrue, ctx, overrides, depth, visiting)?;
+                let bfalse = self.stmts(bfalse, ctx, overrides, depth, visiting)?;
+                Ok(quote! {
+                    if #cond { #btrue } else { #bfalse }
+                })
+            }
+            Expression::Range(over, body, _) => match **over {
+                Expression::Variable(..) => {
+                    let items = value(over, ctx)?;
+                    let item = format_ident!("item_{}", depth);
+                    let body = self.stmts(body, &quote!(#item), overrides, depth + 1, visiting)?;
+                    Ok(quote! {
+                        for #item in ::std::iter::IntoIterator::into_iter(&#items) { #body }
+                    })
+                }
+                Expression::Number(num, _) => {
+                    let num = num
+                        .parse::<u64>()
+                        .map_err(|_| "range literal required a valid number".to_owned())?;
+                    let body = self.stmts(body, ctx, overrides, depth, visiting)?;
+                    Ok(quote! {
+                        for _ in 0..#num { #body }
+                    })
+                }
+                _ => Err("Can only range over vars or numbers".to_owned()),
+            },
+        }
+    }
+}
+
+/// a var path like `.user.name` or `.items.0` becomes `ctx.user.name` or `ctx.items[0]`
+fn value(expr: &Expression<'_>, ctx: &TokenStream) -> Result<TokenStream, String> {
+    match expr {
+        Expression::Variable(path, _) => {
+            let mut code = ctx.clone();
+            for segment in path.split('.').filter(|s| !s.is_empty()) {
+                code = match segment.parse::<usize>() {
+                    Ok(index) => quote!(#code[#index]),
+                    Err(_) => {
+                        let field = field(segment)?;
+                        quote!(#code.#field)
+                    }
+                };
+            }
+            Ok(code)
+        }
+        Expression::Literal(lit, _) => Ok(quote!(#lit)),
+        Expression::Number(num, _) => {
+            let (negative, digits) = match num.strip_prefix('-') {
+                Some(digits) => (true, digits),
+                None => (false, *num),
+            };
+            // suffixed like the json renderer reads them, so big literals stay valid
+            let lit = match digits.contains('.') {
+                true => digits.parse::<f64>().map(Literal::f64_suffixed).ok(),
+                false => (digits.parse::<i64>().map(Literal::i64_suffixed).ok())
+                    .or_else(|| digits.parse::<u64>().map(Literal::u64_suffixed).ok())
+                    .or_else(|| digits.parse::<f64>().map(Literal::f64_suffixed).ok()),
+            }
+            .ok_or_else(|| format!("invalid number '{}'", num))?;
+            match negative {
+                true => Ok(quote!((-#lit))),
+                false => Ok(quote!(#lit)),
+            }
+        }
+        Expression::Arithmetic(left, op, right, span) => {
+            let left = operand(left, ctx)?;
+            let right = operand(right, ctx)?;
+            let op = match op {
+                ArithOp::Add => quote!(Add),
+                ArithOp::Sub => quote!(Sub),
+                ArithOp::Mul => quote!(Mul),
+                ArithOp::Div => quote!(Div),
+                ArithOp::Rem => quote!(Rem),
+            };
+            let span = template_span(*span);
+            Ok(quote! {
+                ::templus::typed::arith(
+                    &#left,
+                    ::templus::compiler::parser::ArithOp::#op,
+                    &#right,
+                    #span,
+                )?
+            })
+        }
+        Expression::Negate(inner, span) => {
+            let inner = operand(inner, ctx)?;
+            let span = template_span(*span);
+            Ok(quote!(::templus::typed::negate(&#inner, #span)?))
+        }
+        Expression::Safe(inner, _) => value(inner, ctx),
+        Expression::If(..) | Expression::Range(..) => Err("expected a value".to_owned()),
+    }
+}
+
+/// like `value`, but `'true'` and `'false'` are bools, as they are for the json renderer
+fn operand(expr: &Expression<'_>, ctx: &TokenStream) -> Result<TokenStream, String> {
+    match expr {
+        Expression::Literal(lit, _) => match lit.parse::<bool>() {
+            Ok(bool) => Ok(quote!(#bool)),
+            Err(_) => Ok(quote!(#lit)),
+        },
+        _ => value(expr, ctx),
+    }
+}
+
+fn condition(ifexpr: &IfExpr<'_>, ctx: &TokenStream) -> Result<TokenStream, String> {
+    let (op, right) = match (ifexpr.op(), ifexpr.right()) {
+        (Some(op), Some(right)) => (op, right),
+        _ => {
+            let left = value(ifexpr.left(), ctx)?;
+            return Ok(quote!(::templus::typed::Truthy::truthy(&#left)));
+        }
+    };
+    let op = match op {
+        Op::In => {
+            let (left, right) = (value(ifexpr.left(), ctx)?, value(right, ctx)?);
+            return Ok(quote!(::templus::typed::Contains::contains_value(&#right, &#left)));
+        }
+        Op::Contains => {
+            let (left, right) = (value(ifexpr.left(), ctx)?, value(right, ctx)?);
+            return Ok(quote!(::templus::typed::Contains::contains_value(&#left, &#right)));
+        }
+        Op::Eq => quote!(Eq),
+        Op::Neq => quote!(Neq),
+        Op::Gt => quote!(Gt),
+        Op::Gte => quote!(Gte),
+        Op::Lt => quote!(Lt),
+        Op::Lte => quote!(Lte),
+    };
+    let (left, right) = (operand(ifexpr.left(), ctx)?, operand(right, ctx)?);
+    let span = template_span(ifexpr.span());
+    Ok(quote! {
+        ::templus::typed::compare(&#left, ::templus::compiler::parser::Op::#op, &#right, #span)?
+    })
+}
+
+/// where an expression sits in its template, for errors raised while rendering
+fn template_span(span: templus::compiler::lexer::Span) -> TokenStream {
+    let (line, column) = (span.line(), span.column());
+    quote!(::templus::compiler::lexer::Span::new(#line, #column))
+}
+
+/// field names that are rust keywords are accessed as raw identifiers
+fn field(name: &str) -> Result<Ident, String> {
+    if let Ok(ident) = syn::parse_str::<Ident>(name) {
+        return Ok(ident);
+    }
+    let valid = name.starts_with(|c: char| c == '_' || c.is_alphabetic())
+        && name.chars().all(|c| c == '_' || c.is_alphanumeric());
+    match valid && !matches!(name, "self" | "Self" | "super" | "crate" | "_") {
+        true => Ok(Ident::new_raw(name, Span::call_site())),
+        false => Err(format!("'{}' is not a valid field name", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(input: &str) -> Result<String, String> {
+        let input = syn::parse_str::<Input>(input).unwrap();
+        expand(input).map(|code| code.to_string()).map_err(|err| {
+            err.into_iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+    }
+
+    #[test]
+    fn generates_field_access() {
+        let code = expand_str(r#""tests/templates", "page" => Page"#).unwrap();
+        assert!(code.contains("impl :: templus :: typed :: Template for Page"));
+        assert!(code.contains("self . title"));
+        assert!(code.contains("for item_0 in"));
+    }
+
+    #[test]
+    fn reports_template_errors() {
+        let errors = expand_str(r#""tests/broken", "a" => A"#).unwrap_err();
+        assert!(errors.contains("unclosed `define`"), "{}", errors);
+        assert!(errors.contains("unexpected `end`"), "{}", errors);
+
+        let errors = expand_str(r#""tests/templates", "missing" => A"#).unwrap_err();
+        assert_eq!(errors, "template 'missing' not found");
+    }
+
+    #[test]
+    fn raw_field_names() {
+        assert_eq!(field("name").unwrap().to_string(), "name");
+        assert_eq!(field("type").unwrap().to_string(), "r#type");
+        assert!(field("self").is_err());
+        assert!(field("1a").is_err());
+    }
+}
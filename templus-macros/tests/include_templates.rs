@@ -0,0 +1,142 @@
+use serde::Serialize;
+use templus::{renderer::Environment, typed::Template, value::SafeHtml};
+
+#[derive(Serialize)]
+struct User {
+    name: String,
+    age: u32,
+}
+
+#[derive(Serialize)]
+struct Page {
+    title: String,
+    admin: bool,
+    users: Vec<User>,
+    tags: Vec<String>,
+    count: i64,
+    bio: SafeHtml,
+    note: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Footer {
+    title: &'static str,
+}
+
+#[derive(Serialize)]
+struct Leaf {
+    title: &'static str,
+}
+
+#[derive(Serialize)]
+struct Shop {
+    title: String,
+    price: f64,
+    stock: u32,
+    per_box: i64,
+    sale: Option<bool>,
+}
+
+templus_macros::include_templates!(
+    "tests/templates",
+    "page" => Page,
+    "footer" => Footer,
+    "leaf" => Leaf,
+    "shop" => Shop,
+);
+
+fn page(admin: bool) -> Page {
+    Page {
+        title: "hello".to_owned(),
+        admin,
+        users: vec![
+            User {
+                name: "lommix".to_owned(),
+                age: 30,
+            },
+            User {
+                name: "kid".to_owned(),
+                age: 9,
+            },
+        ],
+        tags: vec!["rust".to_owned(), "web".to_owned()],
+        count: 21,
+        bio: SafeHtml::from("<em>hi</em>"),
+        note: admin.then(|| "<3 & more".to_owned()),
+    }
+}
+
+#[test]
+fn matches_json_renderer() {
+    let source = std::fs::read_to_string("tests/templates/page.html").unwrap();
+    let mut env = Environment::new();
+    env.parse(&source).unwrap();
+
+    for admin in [true, false] {
+        let page = page(admin);
+        let expected = env.render("page", &page).unwrap();
+        assert_eq!(page.render().unwrap(), expected);
+        assert!(expected.contains("<em>hi</em>"));
+    }
+
+    let footer = Footer { title: "bye" };
+    assert_eq!(footer.render().unwrap(), "<footer>bye</footer>");
+}
+
+#[test]
+fn outermost_blocks_win() {
+    let source = std::fs::read_to_string("tests/templates/page.html").unwrap();
+    let mut env = Environment::new();
+    env.parse(&source).unwrap();
+
+    let leaf = Leaf { title: "leaf" };
+    let expected = env.render("leaf", &leaf).unwrap();
+    assert_eq!(leaf.render().unwrap(), expected);
+    assert!(expected.contains("<title>leaf</title>") && expected.contains("mid body"));
+}
+
+#[test]
+fn operators_borrow_and_coerce() {
+    let source = std::fs::read_to_string("tests/templates/shop.html").unwrap();
+    let mut env = Environment::new();
+    env.parse(&source).unwrap();
+
+    for price in [2.0, 4.5] {
+        let shop = Shop {
+            title: "<shop>".to_owned(),
+            price,
+            stock: 7,
+            per_box: 2,
+            sale: Some(true),
+        };
+        let expected = env.render("shop", &shop).unwrap();
+        assert_eq!(shop.render().unwrap(), expected);
+    }
+    let shop = Shop {
+        title: "shop".to_owned(),
+        price: 4.5,
+        stock: 7,
+        per_box: 2,
+        sale: None,
+    };
+    let out = shop.render().unwrap();
+    assert!(
+        out.contains("<h1>shop!</h1>") && out.contains("pricey"),
+        "{}",
+        out
+    );
+    assert!(out.contains("<p>9.0 11.5 -7 3</p>"), "{}", out);
+}
+
+#[test]
+fn arithmetic_errors_instead_of_panics() {
+    let shop = Shop {
+        title: "shop".to_owned(),
+        price: 1.0,
+        stock: 7,
+        per_box: 0,
+        sale: None,
+    };
+    let err = shop.render().unwrap_err();
+    assert!(err.to_string().contains("division by zero"), "{}", err);
+}
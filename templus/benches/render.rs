@@ -0,0 +1,99 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde::Serialize;
+use templus::renderer::Environment;
+
+const TEMPLATES: [(&str, &str, &str); 3] = [
+    ("example", include_str!("../examples/example.html"), "foo"),
+    ("1.html", include_str!("../1.html"), "foo"),
+    ("2.html", include_str!("../2.html"), "test"),
+];
+
+fn render(c: &mut Criterion) {
+    let ctx = templus::context! {
+        name => "lommix",
+        number => 69,
+        bool => true,
+        admin => true
+    }
+    .unwrap();
+
+    let mut group = c.benchmark_group("render");
+    for (file, source, name) in TEMPLATES {
+        let mut tree = Environment::new();
+        tree.parse(source).unwrap();
+        let mut flat = Environment::new();
+        flat.parse(source).unwrap();
+        flat.flatten();
+        assert_eq!(
+            tree.render(name, &ctx).unwrap(),
+            flat.render(name, &ctx).unwrap()
+        );
+
+        group.bench_with_input(BenchmarkId::new("tree", file), &ctx, |b, ctx| {
+            b.iter(|| tree.render(name, ctx).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("flattened", file), &ctx, |b, ctx| {
+            b.iter(|| flat.render(name, ctx).unwrap())
+        });
+    }
+    group.finish();
+}
+
+const LIST: &str = "{{ define 'list' }}<ul>{{ range .users }}\
+    <li>{{ .name }} {{ .age + 1 }}{{ if .admin }} admin{{ end }}\
+    {{ range .tags }} #{{ .name }}{{ end }}</li>{{ end }}</ul>{{ end }}";
+
+#[derive(Serialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Serialize)]
+struct User {
+    name: String,
+    age: u32,
+    admin: bool,
+    tags: Vec<Tag>,
+}
+
+#[derive(Serialize)]
+struct List {
+    users: Vec<User>,
+}
+
+fn range(c: &mut Criterion) {
+    let list = List {
+        users: (0..1000)
+            .map(|i| User {
+                name: format!("user {}", i),
+                age: i % 90,
+                admin: i % 7 == 0,
+                tags: (0..4)
+                    .map(|t| Tag {
+                        name: format!("tag{}", t),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+
+    let mut tree = Environment::new();
+    tree.parse(LIST).unwrap();
+    let mut flat = Environment::new();
+    flat.parse(LIST).unwrap();
+    flat.flatten();
+    assert_eq!(
+        tree.render("list", &list).unwrap(),
+        flat.render("list", &list).unwrap()
+    );
+
+    let mut group = c.benchmark_group("range");
+    group.bench_function("tree", |b| b.iter(|| tree.render("list", &list).unwrap()));
+    group.bench_function("flattened", |b| {
+        b.iter(|| flat.render("list", &list).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render, range);
+criterion_main!(benches);
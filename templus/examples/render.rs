@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 #[derive(serde::Serialize)]
 struct Context {
     name: String,
@@ -18,7 +19,8 @@ fn main() {
                 name => "lommix",
                 number => 69,
                 bool => true
-            },
+            }
+            .unwrap(),
         )
         .unwrap();
 
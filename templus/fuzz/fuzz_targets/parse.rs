@@ -0,0 +1,44 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use templus::{
+    compiler::parser::Parser,
+    renderer::{Environment, UndefinedBehavior},
+};
+
+// any input has to come back as templates, output or an error, never a panic
+fuzz_target!(|data: &[u8]| {
+    let _ = Parser::new(data).parse();
+
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    // big or nested ranges only make the output huge, not wrong
+    let mut digits = source.split(|c: char| !c.is_ascii_digit());
+    if source.matches("range").count() > 2 || digits.any(|num| num.len() > 3) {
+        return;
+    }
+    let ctx = templus::context! {
+        a => 1,
+        b => "<b>",
+        list => vec![1, 2, 3],
+        nested => { a => true, list => vec!["x"] },
+    }
+    .unwrap();
+
+    for undefined in [UndefinedBehavior::Strict, UndefinedBehavior::Lenient] {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(undefined);
+        if env.parse(source).is_err() {
+            return;
+        }
+        let names = env.templates.keys().cloned().collect::<Vec<_>>();
+        for name in &names {
+            let _ = env.render(name, &ctx);
+        }
+        env.flatten();
+        for name in &names {
+            let _ = env.render(name, &ctx);
+        }
+    }
+});
@@ -0,0 +1,245 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::value::Value;
+
+/// Rendered output by template name and context, least recently used entries
+/// are dropped once either limit is reached. Bytes count the output and an estimate
+/// of the context kept to tell entries apart.
+pub(crate) struct RenderCache {
+    max_entries: usize,
+    max_bytes: usize,
+    bytes: usize,
+    len: usize,
+    /// bumped on every access, entries remember when they were last used
+    tick: u64,
+    /// entries whose name and context hash the same share a bucket
+    buckets: HashMap<u64, Vec<Entry>>,
+    /// bucket of every entry by the tick it was last used at, oldest first
+    recency: BTreeMap<u64, u64>,
+}
+
+struct Entry {
+    template: String,
+    ctx: Value,
+    output: String,
+    /// what the entry counts against the byte limit
+    size: usize,
+    last_used: u64,
+}
+
+impl RenderCache {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            bytes: 0,
+            len: 0,
+            tick: 0,
+            buckets: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, template: &str, ctx: &Value) -> Option<String> {
+        self.tick += 1;
+        let tick = self.tick;
+        let key = key(template, ctx);
+        let entry = self
+            .buckets
+            .get_mut(&key)?
+            .iter_mut()
+            .find(|entry| entry.template == template && entry.ctx == *ctx)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(tick, key);
+        entry.last_used = tick;
+        Some(entry.output.clone())
+    }
+
+    pub fn insert(&mut self, template: &str, ctx: Value, output: String) {
+        let size = size(template, &ctx, &output);
+        // too big to ever fit, keeping it would only flush everything else
+        if size > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+        self.tick += 1;
+        let key = key(template, &ctx);
+        let bucket = self.buckets.entry(key).or_default();
+        if let Some(i) = bucket
+            .iter()
+            .position(|entry| entry.template == template && entry.ctx == ctx)
+        {
+            let old = bucket.swap_remove(i);
+            self.recency.remove(&old.last_used);
+            self.bytes -= old.size;
+            self.len -= 1;
+        }
+        self.bytes += size;
+        self.len += 1;
+        bucket.push(Entry {
+            template: template.to_owned(),
+            ctx,
+            output,
+            size,
+            last_used: self.tick,
+        });
+        self.recency.insert(self.tick, key);
+        while self.len > self.max_entries || self.bytes > self.max_bytes {
+            if !self.evict_oldest() {
+                break;
+            }
+        }
+    }
+
+    /// drops everything rendered from `template`
+    pub fn invalidate(&mut self, template: &str) {
+        for bucket in self.buckets.values_mut() {
+            bucket.retain(|entry| {
+                let keep = entry.template != template;
+                if !keep {
+                    self.recency.remove(&entry.last_used);
+                    self.bytes -= entry.size;
+                    self.len -= 1;
+                }
+                keep
+            });
+        }
+        self.buckets.retain(|_, bucket| !bucket.is_empty());
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.recency.clear();
+        self.bytes = 0;
+        self.len = 0;
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// drops the least recently used entry, false if there is none
+    fn evict_oldest(&mut self) -> bool {
+        let Some((tick, key)) = self.recency.pop_first() else {
+            return false;
+        };
+        let Some(bucket) = self.buckets.get_mut(&key) else {
+            return false;
+        };
+        let Some(i) = bucket.iter().position(|entry| entry.last_used == tick) else {
+            return false;
+        };
+        let entry = bucket.swap_remove(i);
+        self.bytes -= entry.size;
+        self.len -= 1;
+        if bucket.is_empty() {
+            self.buckets.remove(&key);
+        }
+        true
+    }
+}
+
+/// bytes an entry holds on to, the context is estimated from its strings and values
+fn size(template: &str, ctx: &Value, output: &str) -> usize {
+    template.len() + value_size(ctx) + output.len()
+}
+
+fn value_size(value: &Value) -> usize {
+    std::mem::size_of::<Value>()
+        + match value {
+            Value::String(string) | Value::SafeHtml(string) => string.len(),
+            Value::Array(items) => items.iter().map(value_size).sum(),
+            Value::Object(map) => map.iter().map(|(k, v)| k.len() + value_size(v)).sum(),
+            Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+        }
+}
+
+fn key(template: &str, ctx: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    template.hash(&mut hasher);
+    ctx.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(n: i64) -> Value {
+        Value::from(vec![n])
+    }
+
+    #[test]
+    fn cache_hits_by_template_and_context() {
+        let mut cache = RenderCache::new(10, 1000);
+        cache.insert("a", ctx(1), "a1".to_owned());
+        cache.insert("b", ctx(1), "b1".to_owned());
+        assert_eq!(cache.get("a", &ctx(1)).as_deref(), Some("a1"));
+        assert_eq!(cache.get("b", &ctx(1)).as_deref(), Some("b1"));
+        assert_eq!(cache.get("a", &ctx(2)), None);
+
+        cache.insert("a", ctx(1), "again".to_owned());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("a", &ctx(1)).as_deref(), Some("again"));
+
+        cache.invalidate("a");
+        assert_eq!(cache.get("a", &ctx(1)), None);
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert_eq!(cache.get("b", &ctx(1)), None);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = RenderCache::new(2, 1000);
+        cache.insert("a", ctx(1), "1".to_owned());
+        cache.insert("a", ctx(2), "2".to_owned());
+        cache.get("a", &ctx(1));
+        cache.insert("a", ctx(3), "3".to_owned());
+        assert!(cache.get("a", &ctx(1)).is_some());
+        assert!(cache.get("a", &ctx(2)).is_none());
+        assert!(cache.get("a", &ctx(3)).is_some());
+
+        let entry = size("a", &ctx(1), "123");
+        let mut cache = RenderCache::new(10, entry * 2 - 1);
+        cache.insert("a", ctx(1), "123".to_owned());
+        cache.insert("a", ctx(2), "456".to_owned());
+        assert!(cache.get("a", &ctx(1)).is_none());
+        cache.insert("a", ctx(3), "1".repeat(entry * 2));
+        assert!(cache.get("a", &ctx(2)).is_some());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn cache_counts_context_bytes() {
+        let mut cache = RenderCache::new(10, 1000);
+        let big = Value::from(vec!["x".repeat(1000)]);
+        cache.insert("a", big.clone(), "short".to_owned());
+        assert!(cache.get("a", &big).is_none());
+
+        cache.insert("a", ctx(1), "1".to_owned());
+        assert_eq!(cache.bytes, size("a", &ctx(1), "1"));
+        cache.invalidate("a");
+        assert_eq!(cache.bytes, 0);
+    }
+
+    #[test]
+    fn cache_keeps_recency_in_sync() {
+        let mut cache = RenderCache::new(50, 1000);
+        for i in 0..500 {
+            let template = ["a", "b", "c"][i % 3];
+            cache.insert(template, ctx(i as i64 % 80), i.to_string());
+            cache.get("a", &ctx(i as i64 % 13));
+            if i % 97 == 0 {
+                cache.invalidate("b");
+            }
+            assert_eq!(cache.recency.len(), cache.len());
+        }
+        assert!(cache.len() <= 50);
+        // the most recently inserted entries survive
+        assert!(cache.get("b", &ctx(499 % 80)).is_some());
+    }
+}
@@ -0,0 +1,67 @@
+use super::{error::TemplusError, parser::Statement};
+
+/// every bundle starts with these bytes
+const MAGIC: &[u8; 4] = b"TPLS";
+/// bump whenever the layout of the parse tree changes, old bundles are rejected
+pub const FORMAT_VERSION: u16 = 2;
+
+const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// serializes parsed templates into a bundle that `from_bytes` can load without parsing
+pub fn to_bytes(templates: &[&Statement<'_>]) -> Result<Vec<u8>, TemplusError> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, templates)
+        .map_err(|err| TemplusError::DeafultError(format!("failed to write bundle: {}", err)))?;
+    Ok(bytes)
+}
+
+/// loads templates from a bundle, template text is borrowed from `bytes`
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<Statement<'_>>, TemplusError> {
+    if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+        return Err(TemplusError::DeafultError(
+            "not a templus bundle".to_owned(),
+        ));
+    }
+    let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+    if version != FORMAT_VERSION {
+        return Err(TemplusError::DeafultError(format!(
+            "bundle format version {} is not supported, expected {}",
+            version, FORMAT_VERSION
+        )));
+    }
+    bincode::deserialize(&bytes[HEADER_LEN..])
+        .map_err(|err| TemplusError::DeafultError(format!("corrupt bundle: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::Parser;
+
+    #[test]
+    fn bundle_roundtrip() {
+        let tmpl = std::fs::read_to_string("1.html").unwrap();
+        let templates = Parser::new(tmpl.as_bytes()).parse().unwrap();
+        let bytes = to_bytes(&templates.iter().collect::<Vec<_>>()).unwrap();
+        let loaded = from_bytes(&bytes).unwrap();
+        assert_eq!(format!("{:?}", loaded), format!("{:?}", templates));
+    }
+
+    #[test]
+    fn bundle_rejects_bad_headers() {
+        assert!(from_bytes(b"").is_err());
+        assert!(from_bytes(b"<html></html>").is_err());
+
+        let mut bytes = to_bytes(&[]).unwrap();
+        bytes[MAGIC.len()] = bytes[MAGIC.len()].wrapping_add(1);
+        match from_bytes(&bytes) {
+            Err(TemplusError::DeafultError(msg)) => assert!(msg.contains("version")),
+            other => panic!("{:?}", other),
+        }
+
+        let mut bytes = to_bytes(&[]).unwrap();
+        bytes.truncate(HEADER_LEN);
+        assert!(from_bytes(&bytes).is_err());
+    }
+}
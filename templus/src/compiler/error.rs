@@ -5,7 +5,23 @@ pub enum TemplusError {
     DeafultError(String),
     SyntaxError((String, Span)),
     ParserError(Span),
-    LexerError(Span),
+    LexerError((String, Span)),
+    RenderError((String, Span)),
+    TypeError((String, Span)),
+}
+
+impl TemplusError {
+    /// where the error happened, if it is tied to a place in a template
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            TemplusError::DeafultError(_) => None,
+            TemplusError::SyntaxError((_, at))
+            | TemplusError::LexerError((_, at))
+            | TemplusError::RenderError((_, at))
+            | TemplusError::TypeError((_, at))
+            | TemplusError::ParserError(at) => Some(*at),
+        }
+    }
 }
 
 impl std::error::Error for TemplusError {}
@@ -16,7 +32,9 @@ impl std::fmt::Display for TemplusError {
             TemplusError::DeafultError(msg) => write!(f, "{}", msg),
             TemplusError::SyntaxError((msg, at)) => write!(f, "{} ,at:{}", msg, at),
             TemplusError::ParserError(at) => write!(f, "Parser error at: {}", at),
-            TemplusError::LexerError(at) => write!(f, "Lexer error at: {}", at),
+            TemplusError::LexerError((msg, at)) => write!(f, "{} ,at:{}", msg, at),
+            TemplusError::RenderError((msg, at)) => write!(f, "{} ,at:{}", msg, at),
+            TemplusError::TypeError((msg, at)) => write!(f, "Type error: {} ,at:{}", msg, at),
         }
     }
 }
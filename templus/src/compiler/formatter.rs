@@ -0,0 +1,178 @@
+use super::{
+    error::TemplusError,
+    lexer::{Lexer, Syntax},
+    tokens::Token,
+};
+
+/// Rewrites directives as `{{ .a + 1 }}` with single spaces and indents lines holding
+/// only a directive by how deep it is nested. Html and raw blocks stay untouched.
+pub fn format(source: &str, syntax: &Syntax, indent: &str) -> Result<String, TemplusError> {
+    let mut lexer = Lexer::with_syntax(source.as_bytes(), syntax.clone())?;
+    let mut out = String::with_capacity(source.len());
+    // everything before this offset is already in `out`
+    let mut copied = 0;
+    let mut depth = 0usize;
+    let mut in_raw = false;
+
+    while let Some(result) = lexer.next_with_range() {
+        let (token, range) = result?;
+        // html is copied as is once the directive after it shows up
+        if !matches!(token, Token::CodeStart | Token::Comment(_)) {
+            continue;
+        }
+        let start = range.start + source[range.clone()].find(&syntax.block_start).unwrap_or(0);
+        out += &source[copied..start];
+
+        if let Token::Comment(_) = token {
+            indent_line(&mut out, indent, depth);
+            out += &source[start..range.end];
+            copied = range.end;
+            continue;
+        }
+
+        let trim_start =
+            source[start + syntax.block_start.len()..range.end].starts_with(&syntax.trim_marker);
+        let mut body = String::new();
+        let mut first = None;
+        let mut space_before_next = false;
+        let mut last_was_operand = false;
+        let (trim_end, end) = loop {
+            let (token, range) = match lexer.next_with_range() {
+                Some(result) => result?,
+                None => return Ok(out + &source[start..]),
+            };
+            let text = source[range.clone()].trim_start();
+            if token == Token::CodeEnd {
+                break (text.starts_with(&syntax.trim_marker), range.end);
+            }
+            if space_before_next && token != Token::RParen {
+                body.push(' ');
+            }
+            body += text;
+            // no space inside parens or after a sign
+            space_before_next =
+                !(token == Token::LParen || (token == Token::Minus && !last_was_operand));
+            last_was_operand = token.is_operand();
+            first.get_or_insert(token);
+        };
+
+        let level = match first {
+            Some(Token::End) => {
+                depth = depth.saturating_sub(1);
+                depth
+            }
+            Some(Token::Else) => depth.saturating_sub(1),
+            _ => depth,
+        };
+        // whitespace in front of the `end` of a raw block belongs to its content
+        match (&first, in_raw) {
+            (Some(Token::End), true) => in_raw = false,
+            _ => indent_line(&mut out, indent, level),
+        }
+        match first {
+            Some(Token::Define | Token::Block | Token::If | Token::Range) => depth += 1,
+            Some(Token::Raw) => {
+                depth += 1;
+                in_raw = true;
+            }
+            _ => (),
+        }
+
+        out += &syntax.block_start;
+        if trim_start {
+            out += &syntax.trim_marker;
+        }
+        out.push(' ');
+        out += &body;
+        out.push(' ');
+        if trim_end {
+            out += &syntax.trim_marker;
+        }
+        out += &syntax.block_end;
+        copied = end;
+    }
+    out += &source[copied..];
+    Ok(out)
+}
+
+/// re-indents the current line if nothing but whitespace is on it yet
+fn indent_line(out: &mut String, indent: &str, level: usize) {
+    let line_start = out.rfind('\n').map_or(0, |i| i + 1);
+    if out[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+        out.truncate(line_start);
+        *out += &indent.repeat(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(source: &str) -> String {
+        format(source, &Syntax::default(), "\t").unwrap()
+    }
+
+    #[test]
+    fn format_directives() {
+        assert_eq!(fmt("<p>{{.name}}</p>"), "<p>{{ .name }}</p>");
+        assert_eq!(
+            fmt("{{-   .a+(.b*-2)   -}}{{ if .x>=1}}{{-  .y}}{{end}}"),
+            "{{- .a + (.b * -2) -}}{{ if .x >= 1 }}{{- .y }}{{ end }}"
+        );
+        assert_eq!(fmt("{{ - .a }}"), "{{ -.a }}");
+        assert_eq!(fmt("{{.bio|safe}}"), "{{ .bio | safe }}");
+        assert_eq!(fmt("{{ .a - -1 }}"), "{{ .a - -1 }}");
+        assert_eq!(
+            fmt("{{define \"a\"  extends 'b'}}{{   /* keep  me */}}{{end}}"),
+            "{{ define \"a\" extends 'b' }}{{   /* keep  me */}}{{ end }}"
+        );
+    }
+
+    #[test]
+    fn format_indentation() {
+        let source = "{{ define 'a' }}\n\
+                      <div>\n\
+                      {{if .a}}\n\
+                      \x20   <p>{{ .a }}</p>\n\
+                      \x20     {{ else }}\n\
+                      {{ /* why */ }}\n\
+                      {{ range .items }}{{ . }}{{ end }}\n\
+                      {{end}}\n\
+                      \x20 {{ raw }}\n\
+                      \x20 {{ not . code }}\n\
+                      \x20 {{ end }}\n\
+                      </div>\n\
+                      {{ end }}\n";
+        let expected = "{{ define 'a' }}\n\
+                        <div>\n\
+                        \t{{ if .a }}\n\
+                        \x20   <p>{{ .a }}</p>\n\
+                        \t{{ else }}\n\
+                        \t\t{{ /* why */ }}\n\
+                        \t\t{{ range .items }}{{ . }}{{ end }}\n\
+                        \t{{ end }}\n\
+                        \t{{ raw }}\n\
+                        \x20 {{ not . code }}\n\
+                        \x20 {{ end }}\n\
+                        </div>\n\
+                        {{ end }}\n";
+        assert_eq!(fmt(source), expected);
+        assert_eq!(fmt(expected), expected);
+    }
+
+    #[test]
+    fn format_example() {
+        let source = std::fs::read_to_string("examples/example.html").unwrap();
+        let formatted = fmt(&source);
+        assert!(!formatted.contains("{{end}}"));
+        assert!(
+            formatted.contains("\t{{ block 'meta' }}\n\t\t<title>jsx sucks</title>\n\t{{ end }}")
+        );
+        assert_eq!(fmt(&formatted), formatted);
+    }
+
+    #[test]
+    fn format_rejects_broken_templates() {
+        assert!(format("{{ 'open }}", &Syntax::default(), "\t").is_err());
+    }
+}
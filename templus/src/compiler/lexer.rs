@@ -1,22 +1,142 @@
+use serde::{Deserialize, Serialize};
+
 use super::{error::TemplusError, tokens::Token};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Span {
     current_line: usize,
     current_column: usize,
 }
 
+impl Span {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self {
+            current_line: line,
+            current_column: column,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.current_line
+    }
+
+    pub fn column(&self) -> usize {
+        self.current_column
+    }
+}
+
 impl std::fmt::Display for Span {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}:{}", self.current_line, self.current_column)
     }
 }
 
+/// The delimiters templates are written with, `{{ }}`, `{{/* */}}` and `-` by default.
+/// Swap them when templus collides with a client side framework.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Syntax {
+    pub block_start: String,
+    pub block_end: String,
+    pub comment_start: String,
+    pub comment_end: String,
+    pub trim_marker: String,
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        Self {
+            block_start: "{{".to_owned(),
+            block_end: "}}".to_owned(),
+            comment_start: "/*".to_owned(),
+            comment_end: "*/".to_owned(),
+            trim_marker: "-".to_owned(),
+        }
+    }
+}
+
+impl Syntax {
+    pub fn new(block_start: &str, block_end: &str) -> Self {
+        Self {
+            block_start: block_start.to_owned(),
+            block_end: block_end.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_comments(mut self, comment_start: &str, comment_end: &str) -> Self {
+        self.comment_start = comment_start.to_owned();
+        self.comment_end = comment_end.to_owned();
+        self
+    }
+
+    pub fn with_trim_marker(mut self, trim_marker: &str) -> Self {
+        self.trim_marker = trim_marker.to_owned();
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), TemplusError> {
+        let delimiters = [
+            &self.block_start,
+            &self.block_end,
+            &self.comment_start,
+            &self.comment_end,
+            &self.trim_marker,
+        ];
+        if delimiters
+            .iter()
+            .any(|delimiter| delimiter.trim().is_empty())
+        {
+            return Err(TemplusError::DeafultError(
+                "delimiters cannot be empty or whitespace".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// length of a trimming block start like `{{- `, the marker needs whitespace after it
+    /// so `{{-3}}` stays a negative number
+    fn trim_start_len(&self, code: &[u8]) -> Option<usize> {
+        let len = self.block_start.len() + self.trim_marker.len();
+        let is_trim = code.starts_with(self.block_start.as_bytes())
+            && code[self.block_start.len()..].starts_with(self.trim_marker.as_bytes())
+            && code.get(len).is_some_and(|b| b.is_ascii_whitespace());
+        is_trim.then_some(len)
+    }
+
+    /// offset of the `{{ end }}` closing a raw block
+    fn find_raw_end(&self, code: &[u8]) -> Option<usize> {
+        let start = self.block_start.as_bytes();
+        let mut offset = 0;
+        loop {
+            offset += find(&code[offset..], start)?;
+            if self.is_end_tag(&code[offset + start.len()..]) {
+                return Some(offset);
+            }
+            offset += start.len();
+        }
+    }
+
+    fn is_end_tag(&self, code: &[u8]) -> bool {
+        let trim = self.trim_marker.as_bytes();
+        let end = self.block_end.as_bytes();
+        let rest = code.strip_prefix(trim).unwrap_or(code).trim_ascii_start();
+        match rest.strip_prefix(b"end") {
+            Some(rest) => {
+                let rest = rest.trim_ascii_start();
+                rest.starts_with(end) || rest.strip_prefix(trim).is_some_and(|r| r.starts_with(end))
+            }
+            None => false,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
+#[allow(clippy::enum_variant_names)]
 enum LexerState {
     #[default]
     InHtml,
     InCode,
+    InRaw,
 }
 
 #[derive(Debug, Default)]
@@ -26,6 +146,10 @@ pub(crate) struct Lexer<'a> {
     line_cursor: usize,
     column_cursor: usize,
     state: LexerState,
+    last_was_operand: bool,
+    trim_next: bool,
+    raw_next: bool,
+    syntax: Syntax,
 }
 
 impl<'a> Lexer<'a> {
@@ -33,27 +157,120 @@ impl<'a> Lexer<'a> {
         Self {
             code,
             line_cursor: 1,
+            column_cursor: 1,
             ..Default::default()
         }
     }
 
+    /// fails on empty delimiters, they would never advance
+    pub fn with_syntax(code: &'a [u8], syntax: Syntax) -> Result<Self, TemplusError> {
+        syntax.validate()?;
+        Ok(Self {
+            syntax,
+            ..Self::new(code)
+        })
+    }
+
     fn skip_whitespace(&mut self) {
         while self.cursor < self.code.len()
             && (self.code[self.cursor].is_ascii_whitespace()
                 || self.code[self.cursor].is_ascii_control())
         {
-            if self.code[self.cursor] == b'\n' {
+            self.advance(1);
+        }
+    }
+
+    /// moves the cursor forward, keeping line and column in sync
+    fn advance(&mut self, bytes: usize) {
+        let end = (self.cursor + bytes).min(self.code.len());
+        for &byte in &self.code[self.cursor..end] {
+            if byte == b'\n' {
                 self.line_cursor += 1;
-                self.column_cursor = 0;
+                self.column_cursor = 1;
+            } else if byte & 0xC0 != 0x80 {
+                // columns count chars, utf-8 continuation bytes don't start one
+                self.column_cursor += 1;
             }
+        }
+        self.cursor = end;
+    }
 
-            self.cursor += 1;
+    /// consumes a `/* ... */}}` right after a block start, comments may span lines
+    fn comment(&mut self, span: Span) -> Option<Result<(Token<'a>, Span), TemplusError>> {
+        let open = self.syntax.comment_start.len();
+        let close = self.syntax.comment_end.len();
+        let start = self.cursor + count_leading_whitespace(&self.code[self.cursor..]);
+        if !self.code[start..].starts_with(self.syntax.comment_start.as_bytes()) {
+            return None;
         }
+        let len = match find(
+            &self.code[start + open..],
+            self.syntax.comment_end.as_bytes(),
+        ) {
+            Some(offset) => offset,
+            None => {
+                self.advance(self.code.len());
+                return error("unclosed comment", span);
+            }
+        };
+        let comment = match std::str::from_utf8(&self.code[start + open..start + open + len]) {
+            Ok(s) => s,
+            Err(_) => {
+                self.advance(start + open + len + close - self.cursor);
+                self.skip_block();
+                return error("invalid utf-8 in comment", span);
+            }
+        };
+        self.advance(start + open + len + close - self.cursor);
+        self.skip_whitespace();
+
+        if let Some(len) = self.trim_end_len() {
+            self.trim_next = true;
+            self.advance(len);
+        } else if self.at(&self.syntax.block_end) {
+            self.advance(self.syntax.block_end.len());
+        } else {
+            let span = self.loc();
+            self.skip_block();
+            return error(
+                format!("expected `{}` after comment", self.syntax.block_end),
+                span,
+            );
+        }
+        Some(Ok((Token::Comment(comment), span)))
     }
 
-    fn advance(&mut self, bytes: usize) {
-        self.cursor += bytes;
-        self.column_cursor += bytes;
+    /// skips past the end of the current block after an error
+    fn skip_block(&mut self) {
+        let end = find(&self.code[self.cursor..], self.syntax.block_end.as_bytes())
+            .map_or(self.code.len(), |offset| {
+                self.cursor + offset + self.syntax.block_end.len()
+            });
+        self.advance(end - self.cursor);
+        self.state = LexerState::InHtml;
+        self.raw_next = false;
+        self.trim_next = false;
+    }
+
+    fn at(&self, delimiter: &str) -> bool {
+        self.code[self.cursor..].starts_with(delimiter.as_bytes())
+    }
+
+    /// length of a trimming block end like ` -}}`, the marker needs whitespace before it
+    fn trim_end_len(&self) -> Option<usize> {
+        let is_trim = self.cursor > 0
+            && self.code[self.cursor - 1].is_ascii_whitespace()
+            && self.at(&self.syntax.trim_marker)
+            && self.code[self.cursor + self.syntax.trim_marker.len()..]
+                .starts_with(self.syntax.block_end.as_bytes());
+        is_trim.then_some(self.syntax.trim_marker.len() + self.syntax.block_end.len())
+    }
+
+    fn state_after_code(&mut self) -> LexerState {
+        match std::mem::take(&mut self.raw_next) {
+            true => LexerState::InRaw,
+            false => LexerState::InHtml,
+        }
     }
 
     fn loc(&self) -> Span {
@@ -64,156 +281,284 @@ impl<'a> Lexer<'a> {
     }
 }
 
+impl<'a> Lexer<'a> {
+    /// like `next`, but keeps comments and reports the bytes each token was read from,
+    /// including whitespace skipped before it
+    pub(crate) fn next_with_range(
+        &mut self,
+    ) -> Option<Result<(Token<'a>, std::ops::Range<usize>), TemplusError>> {
+        let start = self.cursor;
+        let next = self.next_token();
+        if !matches!(next, Some(Ok((Token::Comment(_), _)))) {
+            self.last_was_operand = matches!(&next, Some(Ok((token, _))) if token.is_operand());
+        }
+        next.map(|result| result.map(|(token, _)| (token, start..self.cursor)))
+    }
+}
+
 impl<'a> Iterator for Lexer<'a> {
     type Item = Result<(Token<'a>, Span), TemplusError>;
     fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let next = self.next_token();
+            // comments never leave the lexer
+            if let Some(Ok((Token::Comment(_), _))) = next {
+                continue;
+            }
+            self.last_was_operand = matches!(&next, Some(Ok((token, _))) if token.is_operand());
+            return next;
+        }
+    }
+}
+
+impl<'a> Lexer<'a> {
+    fn next_token(&mut self) -> Option<Result<(Token<'a>, Span), TemplusError>> {
         if self.cursor >= self.code.len() {
-            return None;
+            // report a block left open at the end of the template once, then stop
+            return match std::mem::take(&mut self.state) {
+                LexerState::InHtml => None,
+                LexerState::InCode => error(
+                    format!("unclosed code block, expected `{}`", self.syntax.block_end),
+                    self.loc(),
+                ),
+                LexerState::InRaw => error("unclosed raw block, expected `end`", self.loc()),
+            };
         }
         match self.state {
             LexerState::InHtml => {
-                self.skip_whitespace();
-                if self.cursor >= self.code.len() {
-                    return None;
+                // a `-}}` eats all whitespace up to the next text
+                if self.trim_next {
+                    self.trim_next = false;
+                    self.skip_whitespace();
+                    if self.cursor >= self.code.len() {
+                        return None;
+                    }
                 }
+                let span = self.loc();
                 // find the next punctuation
-                let (start, lines_passed) = match next_block_start(&self.code[self.cursor..]) {
-                    Some((offset, lines)) => (offset, lines),
-                    None => {
-                        let code = btrim(&self.code[self.cursor..self.code.len()]);
-                        let token = Token::Template(std::str::from_utf8(code).unwrap());
-                        let span = self.loc();
-
-                        self.cursor = self.code.len();
-                        return Some(Ok((token, span)));
-                    }
+                let start = find(
+                    &self.code[self.cursor..],
+                    self.syntax.block_start.as_bytes(),
+                )
+                .unwrap_or(self.code.len() - self.cursor);
+                let at_block = self.cursor + start < self.code.len();
+                let trim_len = match at_block {
+                    true => self
+                        .syntax
+                        .trim_start_len(&self.code[self.cursor + start..]),
+                    false => None,
                 };
-                self.line_cursor += lines_passed;
-                // we found html
-                // is it all whitespace?
-                if start > 0 {
-                    if is_whitespace_only(&self.code[self.cursor..self.cursor + start]) {
-                        self.advance(start);
-                    } else {
-                        let code = btrim(&self.code[self.cursor..self.cursor + start]);
-                        let token = Token::Template(std::str::from_utf8(code).unwrap());
-                        let span = self.loc();
-                        self.advance(start);
-                        return Some(Ok((token, span)));
-                    }
+                let trim_before = trim_len.is_some();
+
+                // we found html, whitespace is kept as is unless a `{{-` follows
+                let mut code = &self.code[self.cursor..self.cursor + start];
+                if trim_before {
+                    code = code.trim_ascii_end();
+                }
+                if !code.is_empty() {
+                    let token = match std::str::from_utf8(code) {
+                        Ok(s) => Token::Template(s),
+                        Err(_) => {
+                            self.advance(start);
+                            return error("invalid utf-8 in template", span);
+                        }
+                    };
+                    self.advance(start);
+                    return Some(Ok((token, span)));
+                }
+                self.advance(start);
+                if !at_block {
+                    return None;
                 }
 
                 // we are at the start of a code block
                 // skip block start
-                self.advance(2);
+                let span = self.loc();
+                self.advance(trim_len.unwrap_or(self.syntax.block_start.len()));
+                if let Some(comment) = self.comment(span) {
+                    return Some(comment);
+                }
                 self.state = LexerState::InCode;
-                Some(Ok((Token::CodeStart, self.loc())))
+                Some(Ok((Token::CodeStart, span)))
+            }
+            LexerState::InRaw => {
+                if self.trim_next {
+                    self.trim_next = false;
+                    self.skip_whitespace();
+                }
+                let span = self.loc();
+                // everything up to the closing `{{ end }}` is passed through untouched
+                let end = match self.syntax.find_raw_end(&self.code[self.cursor..]) {
+                    Some(offset) => offset,
+                    None => {
+                        self.state = LexerState::InHtml;
+                        self.advance(self.code.len());
+                        return error("unclosed raw block, expected `end`", span);
+                    }
+                };
+                self.state = LexerState::InHtml;
+
+                let mut code = &self.code[self.cursor..self.cursor + end];
+                if self
+                    .syntax
+                    .trim_start_len(&self.code[self.cursor + end..])
+                    .is_some()
+                {
+                    code = code.trim_ascii_end();
+                }
+                let token = match std::str::from_utf8(code) {
+                    Ok(s) => Token::Template(s),
+                    Err(_) => {
+                        self.advance(end);
+                        return error("invalid utf-8 in raw block", span);
+                    }
+                };
+                self.advance(end);
+                if code.is_empty() {
+                    return self.next_token();
+                }
+                Some(Ok((token, span)))
             }
             LexerState::InCode => {
-                self.skip_whitespace();
-
-                if self.cursor >= self.code.len() {
-                    return None;
+                let token = self.code_token();
+                if let Some(Err(_)) = token {
+                    // resync after the broken block so callers can keep lexing
+                    self.skip_block();
                 }
+                token
+            }
+        }
+    }
 
-                if &self.code[self.cursor..self.cursor + 2] == b"}}" {
-                    self.state = LexerState::InHtml;
-                    self.advance(2);
-                    return Some(Ok((Token::CodeEnd, self.loc())));
-                }
+    fn code_token(&mut self) -> Option<Result<(Token<'a>, Span), TemplusError>> {
+        self.skip_whitespace();
 
-                match &self.code[self.cursor] {
-                    // string literal
-                    b'"' | b'\'' => {
-                        let offset = match offset_to_delimiter(
-                            &self.code[self.cursor + 1..],
-                            self.code[self.cursor],
-                        ) {
-                            Some(offset) => offset,
-                            None => return Some(Err(TemplusError::LexerError(self.loc()))),
-                        };
+        let span = self.loc();
+        if self.cursor >= self.code.len() {
+            return error(
+                format!("unclosed code block, expected `{}`", self.syntax.block_end),
+                span,
+            );
+        }
 
-                        let literal = match std::str::from_utf8(
-                            &self.code[self.cursor + 1..self.cursor + offset + 1],
-                        ) {
-                            Ok(s) => s,
-                            Err(_) => return Some(Err(TemplusError::LexerError(self.loc()))),
-                        };
+        // trim marker, only if separated like in `{{ .name -}}`
+        if let Some(len) = self.trim_end_len() {
+            self.trim_next = true;
+            self.state = self.state_after_code();
+            self.advance(len);
+            return Some(Ok((Token::CodeEnd, span)));
+        }
+        if self.at(&self.syntax.block_end) {
+            self.state = self.state_after_code();
+            self.advance(self.syntax.block_end.len());
+            return Some(Ok((Token::CodeEnd, span)));
+        }
 
-                        self.advance(offset + 2);
-                        return Some(Ok((Token::Literal(literal), self.loc())));
-                    }
-                    // number literal
-                    b'0'..=b'9' => {
-                        let offset = match offset_to_number_end(&self.code[self.cursor..]) {
-                            Some(offset) => offset,
-                            None => return Some(Err(TemplusError::LexerError(self.loc()))),
-                        };
-                        let number = match std::str::from_utf8(
-                            &self.code[self.cursor..self.cursor + offset],
-                        ) {
-                            Ok(s) => s,
-                            Err(_) => return Some(Err(TemplusError::LexerError(self.loc()))),
-                        };
-                        self.advance(offset);
-                        return Some(Ok((Token::Literal(number), self.loc())));
+        match &self.code[self.cursor] {
+            // string literal
+            b'"' | b'\'' => {
+                let offset = match offset_to_delimiter(
+                    &self.code[self.cursor + 1..],
+                    self.code[self.cursor],
+                ) {
+                    Some(offset) => offset,
+                    None => return error("unterminated string literal", span),
+                };
+
+                let literal = match std::str::from_utf8(
+                    &self.code[self.cursor + 1..self.cursor + offset + 1],
+                ) {
+                    Ok(s) => s,
+                    Err(_) => return error("invalid utf-8 in string literal", span),
+                };
+
+                self.advance(offset + 2);
+                Some(Ok((Token::Literal(literal), span)))
+            }
+            // number literal, a leading minus is only a sign if it can't be a binary op
+            b'0'..=b'9' | b'-'
+                if self.code[self.cursor].is_ascii_digit()
+                    || (!self.last_was_operand
+                        && self
+                            .code
+                            .get(self.cursor + 1)
+                            .is_some_and(|b| b.is_ascii_digit())) =>
+            {
+                let offset = offset_to_number_end(&self.code[self.cursor..]);
+                // only ascii digits, a sign and a dot were matched
+                let number = std::str::from_utf8(&self.code[self.cursor..self.cursor + offset])
+                    .unwrap_or_default();
+                self.advance(offset);
+                Some(Ok((Token::Number(number), span)))
+            }
+            // operators
+            b'+' | b'-' | b'*' | b'/' | b'%' | b'(' | b')' | b'=' | b'!' | b'<' | b'>' | b'&'
+            | b'|' => {
+                let len = match self.code.get(self.cursor..self.cursor + 2) {
+                    Some(op) if Token::try_from_bslice(op).is_some() => 2,
+                    _ => 1,
+                };
+                let token = match Token::try_from_bslice(&self.code[self.cursor..self.cursor + len])
+                {
+                    Some(token) => token,
+                    None => {
+                        let op = char::from(self.code[self.cursor]);
+                        return error(format!("unexpected operator `{}`", op), span);
                     }
-                    // var ident
-                    b'.' => {
-                        self.cursor += 1;
-                        let offset =
-                            match offset_to_any_delimiter(&self.code[self.cursor..], &[b' ', b'}'])
-                            {
-                                Some(offset) => offset,
-                                None => return Some(Err(TemplusError::LexerError(self.loc()))),
-                            };
-                        let ident = match std::str::from_utf8(
-                            &self.code[self.cursor..self.cursor + offset],
-                        ) {
-                            Ok(s) => s,
-                            Err(_) => return Some(Err(TemplusError::LexerError(self.loc()))),
+                };
+                self.advance(len);
+                Some(Ok((token, span)))
+            }
+            // var ident
+            b'.' => {
+                self.advance(1);
+                let offset = offset_to_ident_end(&self.code[self.cursor..], true);
+                let ident = match std::str::from_utf8(&self.code[self.cursor..self.cursor + offset])
+                {
+                    Ok(s) => s,
+                    Err(_) => return error("invalid utf-8 in variable name", span),
+                };
+                self.advance(offset);
+                Some(Ok((Token::Var(ident), span)))
+            }
+            // ident token
+            _ => {
+                let offset = offset_to_ident_end(&self.code[self.cursor..], false);
+                let ident = &self.code[self.cursor..self.cursor + offset];
+                let token = match Token::try_from_bslice(ident) {
+                    Some(token) => token,
+                    None if ident.is_empty() => {
+                        let rest = self.code[self.cursor..].utf8_chunks().next();
+                        return match rest.and_then(|chunk| chunk.valid().chars().next()) {
+                            Some(c) => error(format!("unexpected character `{}`", c), span),
+                            None => error("invalid utf-8 in code block", span),
                         };
-                        self.advance(offset);
-                        return Some(Ok((Token::Var(ident), self.loc())));
                     }
-                    // ident token
-                    _ => {
-                        let offset =
-                            match offset_to_any_delimiter(&self.code[self.cursor..], &[b' ', b'}'])
-                            {
-                                Some(offset) => offset,
-                                None => return Some(Err(TemplusError::LexerError(self.loc()))),
-                            };
-
-                        let ident = &self.code[self.cursor..self.cursor + offset];
-                        self.advance(offset);
-                        let token = match Token::try_from_bslice(ident) {
-                            Some(token) => token,
-                            None => return Some(Err(TemplusError::LexerError(self.loc()))),
-                        };
-                        return Some(Ok((token, self.loc())));
+                    None => {
+                        let ident = String::from_utf8_lossy(ident);
+                        return error(format!("unknown keyword `{}`", ident), span);
                     }
+                };
+                if token == Token::Raw {
+                    self.raw_next = true;
                 }
+                self.advance(offset);
+                Some(Ok((token, span)))
             }
         }
     }
 }
 
-fn offset_to_any_delimiter(code: &[u8], delimiters: &[u8]) -> Option<usize> {
-    let mut offset = 0;
-    loop {
-        if offset >= code.len() {
-            return None;
-        }
-
-        for delimiter in delimiters {
-            if Some(delimiter) == code.get(offset) {
-                return Some(offset);
-            }
-        }
-
-        offset += 1;
-    }
+/// idents are ascii alphanumerics, `_` and any utf-8 multibyte char, var paths also allow `.`
+fn offset_to_ident_end(code: &[u8], is_path: bool) -> usize {
+    code.iter()
+        .take_while(|&&byte| {
+            byte.is_ascii_alphanumeric()
+                || byte == b'_'
+                || byte >= 0x80
+                || (is_path && byte == b'.')
+        })
+        .count()
 }
 
 fn offset_to_delimiter(code: &[u8], delimiter: u8) -> Option<usize> {
@@ -229,74 +574,36 @@ fn offset_to_delimiter(code: &[u8], delimiter: u8) -> Option<usize> {
     }
 }
 
-fn offset_to_number_end(code: &[u8]) -> Option<usize> {
+/// matches `-?[0-9]+(\.[0-9]+)?`
+fn offset_to_number_end(code: &[u8]) -> usize {
     let mut offset = 0;
-    loop {
-        if offset >= code.len() {
-            return None;
-        }
-        if let Some(b'0'..=b'9') = code.get(offset) {
-            offset += 1;
-        } else {
-            return Some(offset);
-        }
+    let mut seen_dot = false;
+    if let Some(b'-') = code.first() {
+        offset += 1;
     }
-}
-
-fn next_block_start(code: &[u8]) -> Option<(usize, usize)> {
-    let mut local_offset = 0;
-    let mut lines_passed = 0;
     loop {
-        if local_offset >= code.len() {
-            return None;
-        }
-
-        match code.get(local_offset..local_offset + 2) {
-            Some(b"{{") => return Some((local_offset, lines_passed)),
-            _ => local_offset += 1,
-        }
-
-        if let Some(b'\n') = code.get(local_offset) {
-            lines_passed += 1;
+        match code.get(offset) {
+            Some(b'0'..=b'9') => offset += 1,
+            Some(b'.') if !seen_dot && code.get(offset + 1).is_some_and(|b| b.is_ascii_digit()) => {
+                seen_dot = true;
+                offset += 1;
+            }
+            _ => return offset,
         }
     }
 }
 
-fn is_whitespace_only(code: &[u8]) -> bool {
-    code.iter().all(|&x| x == b' ' || x == b'\t' || x == b'\n')
+fn error<T>(msg: impl Into<String>, span: Span) -> Option<Result<T, TemplusError>> {
+    Some(Err(TemplusError::LexerError((msg.into(), span))))
 }
 
-fn btrim(input: &[u8]) -> &[u8] {
-    if input.is_empty() {
-        return input;
-    }
-    let mut start_offset = 0;
-    let mut end_offset = input.len() - 1;
-
-    loop {
-        if start_offset >= input.len() {
-            break;
-        }
-        if input[start_offset] != b' '
-            && input[start_offset] != b'\t'
-            && input[start_offset] != b'\n'
-        {
-            break;
-        }
-        start_offset += 1;
-    }
+fn count_leading_whitespace(code: &[u8]) -> usize {
+    code.iter().take_while(|b| b.is_ascii_whitespace()).count()
+}
 
-    loop {
-        if end_offset < 1 {
-            break;
-        }
-        if input[end_offset] == b' ' || input[end_offset] == b'\t' || input[end_offset] == b'\n' {
-            end_offset -= 1;
-            continue;
-        }
-        break;
-    }
-    &input[start_offset..end_offset + 1]
+fn find(code: &[u8], needle: &[u8]) -> Option<usize> {
+    code.windows(needle.len())
+        .position(|window| window == needle)
 }
 
 #[cfg(test)]
@@ -316,7 +623,7 @@ mod tests {
             Token::Eq,
             Token::Literal("admin"),
             Token::CodeEnd,
-            Token::Template("<p>Hello</p>"),
+            Token::Template(" <p>Hello</p> "),
             Token::CodeStart,
             Token::End,
             Token::CodeEnd,
@@ -416,7 +723,7 @@ mod tests {
         let expected = vec![
             Token::CodeStart,
             Token::Range,
-            Token::Literal("100"),
+            Token::Number("100"),
             Token::CodeEnd,
         ];
         let count = expected.len();
@@ -448,9 +755,9 @@ mod tests {
             Token::Or,
             Token::Var("id"),
             Token::Gte,
-            Token::Literal("100"),
+            Token::Number("100"),
             Token::CodeEnd,
-            Token::Template("<p>Hello</p>"),
+            Token::Template(" <p>Hello</p> "),
             Token::CodeStart,
             Token::End,
             Token::CodeEnd,
@@ -465,4 +772,244 @@ mod tests {
         });
         assert_eq!(real, count);
     }
+
+    #[test]
+    fn lex_spans() {
+        let tmpl = "<p>\n  {{ if .name }}";
+        let spans = Lexer::new(tmpl.as_bytes())
+            .map(|res| {
+                let (_, span) = res.unwrap();
+                (span.line(), span.column())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![(1, 1), (2, 3), (2, 6), (2, 9), (2, 15)]);
+    }
+
+    #[test]
+    fn lex_numbers() {
+        let tmpl = "{{ if .price >= -3.5 }}{{ range 10 }}";
+        let tokens = Lexer::new(tmpl.as_bytes())
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::CodeStart,
+                Token::If,
+                Token::Var("price"),
+                Token::Gte,
+                Token::Number("-3.5"),
+                Token::CodeEnd,
+                Token::CodeStart,
+                Token::Range,
+                Token::Number("10"),
+                Token::CodeEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_arithmetic() {
+        let tmpl = "{{ (.page+1) * -2 - 3 % .n }}";
+        let tokens = Lexer::new(tmpl.as_bytes())
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::CodeStart,
+                Token::LParen,
+                Token::Var("page"),
+                Token::Plus,
+                Token::Number("1"),
+                Token::RParen,
+                Token::Star,
+                Token::Number("-2"),
+                Token::Minus,
+                Token::Number("3"),
+                Token::Percent,
+                Token::Var("n"),
+                Token::CodeEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_trim_markers() {
+        let tmpl = "a  {{- .x -}}  b {{-3}} c";
+        let tokens = Lexer::new(tmpl.as_bytes())
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Template("a"),
+                Token::CodeStart,
+                Token::Var("x"),
+                Token::CodeEnd,
+                Token::Template("b "),
+                Token::CodeStart,
+                Token::Number("-3"),
+                Token::CodeEnd,
+                Token::Template(" c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_comments() {
+        let tmpl = "<p>{{/* a {{ .fake }}\n comment */}}\n{{- /* trimmed */ -}}\n {{ .name }}";
+        let tokens = Lexer::new(tmpl.as_bytes())
+            .map(|res| {
+                let (token, span) = res.unwrap();
+                (token, span.line(), span.column())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Template("<p>"), 1, 1),
+                (Token::CodeStart, 4, 2),
+                (Token::Var("name"), 4, 5),
+                (Token::CodeEnd, 4, 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_unclosed_comment() {
+        let mut lexer = Lexer::new(b"<p>{{/* never closed }}");
+        assert!(matches!(
+            lexer.nth(1),
+            Some(Err(TemplusError::LexerError(_)))
+        ));
+    }
+
+    #[test]
+    fn lex_raw() {
+        let tmpl = "{{ raw }}<p>{{ message }} {{ endless }}</p>{{ end }}";
+        let tokens = Lexer::new(tmpl.as_bytes())
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::CodeStart,
+                Token::Raw,
+                Token::CodeEnd,
+                Token::Template("<p>{{ message }} {{ endless }}</p>"),
+                Token::CodeStart,
+                Token::End,
+                Token::CodeEnd,
+            ]
+        );
+
+        let mut lexer = Lexer::new(b"{{ raw }}<p>{{ message }}</p>");
+        assert!(matches!(
+            lexer.nth(3),
+            Some(Err(TemplusError::LexerError(_)))
+        ));
+    }
+
+    #[test]
+    fn lex_rejects_empty_delimiters() {
+        assert!(Lexer::with_syntax(b"{{ .a }}", Syntax::new("", "}}")).is_err());
+    }
+
+    #[test]
+    fn lex_custom_syntax() {
+        let syntax = Syntax::new("[[", "]]");
+        let tmpl = "{{ vue }}[[ .a % 2 -]] [[/* c */]][[ raw ]][[ x ]][[ end ]]";
+        let tokens = Lexer::with_syntax(tmpl.as_bytes(), syntax)
+            .unwrap()
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Template("{{ vue }}"),
+                Token::CodeStart,
+                Token::Var("a"),
+                Token::Percent,
+                Token::Number("2"),
+                Token::CodeEnd,
+                Token::CodeStart,
+                Token::Raw,
+                Token::CodeEnd,
+                Token::Template("[[ x ]]"),
+                Token::CodeStart,
+                Token::End,
+                Token::CodeEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_malformed() {
+        let inputs: [&[u8]; 12] = [
+            b"{{",
+            b"{",
+            b"}}",
+            b"{{ .name",
+            b"{{ 'never closed }}",
+            b"{{ # }}",
+            b"{{ unknown }}",
+            b"{{ 12",
+            b"{{ raw }}",
+            b"\xff{{ .a }}",
+            b"{{ .\xff }}",
+            b"{{ \xe4 }}",
+        ];
+        for input in inputs {
+            let results = Lexer::new(input).collect::<Vec<_>>();
+            assert!(results.len() <= input.len() + 1);
+            assert!(
+                results.iter().any(|res| res.is_err()) || input == b"{" || input == b"}}",
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+
+        let mut lexer = Lexer::new(b"{{ .name");
+        assert_eq!(lexer.nth(1).unwrap().unwrap().0, Token::Var("name"));
+        match lexer.next() {
+            Some(Err(TemplusError::LexerError((msg, at)))) => {
+                assert_eq!(msg, "unclosed code block, expected `}}`");
+                assert_eq!(at, Span::new(1, 9));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn lex_recovers_after_error() {
+        let results = Lexer::new(b"{{ # }}<p>{{ .a }}").collect::<Vec<_>>();
+        assert!(matches!(results[1], Err(TemplusError::LexerError(_))));
+        let tokens = results
+            .into_iter()
+            .skip(2)
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Template("<p>"),
+                Token::CodeStart,
+                Token::Var("a"),
+                Token::CodeEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_utf8() {
+        let tmpl = "<p>ä€</p>{{\t.näme\n}}";
+        let tokens = Lexer::new(tmpl.as_bytes())
+            .map(|res| res.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(tokens[1], (Token::CodeStart, Span::new(1, 10)));
+        assert_eq!(tokens[2], (Token::Var("näme"), Span::new(1, 13)));
+        assert_eq!(tokens[3], (Token::CodeEnd, Span::new(2, 1)));
+    }
 }
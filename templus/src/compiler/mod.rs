@@ -2,3 +2,6 @@ pub mod lexer;
 pub mod tokens;
 pub mod error;
 pub mod parser;
+pub mod bundle;
+pub mod formatter;
+pub(crate) mod program;
@@ -1,27 +1,65 @@
-use super::{error::TemplusError, lexer::Lexer};
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    error::TemplusError,
+    lexer::{Lexer, Span, Syntax},
+};
 use crate::compiler::tokens::Token;
+use crate::context::{lookup_var, Context};
+use crate::renderer::{display_value, UndefinedBehavior};
+use crate::value::{Number, Value};
 
-#[derive(Debug)]
+static NULL: Value = Value::Null;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Expression<'a> {
-    Variable(&'a str),
-    Literal(&'a str),
-    If(IfExpr<'a>, Vec<Statement<'a>>, Vec<Statement<'a>>),
-    Range(Box<Expression<'a>>, Vec<Statement<'a>>),
+    Variable(&'a str, Span),
+    Literal(&'a str, Span),
+    Number(&'a str, Span),
+    Arithmetic(
+        #[serde(borrow)] Box<Expression<'a>>,
+        ArithOp,
+        #[serde(borrow)] Box<Expression<'a>>,
+        Span,
+    ),
+    Negate(#[serde(borrow)] Box<Expression<'a>>, Span),
+    /// `{{ .bio | safe }}`, output is not escaped
+    Safe(#[serde(borrow)] Box<Expression<'a>>, Span),
+    If(
+        #[serde(borrow)] IfExpr<'a>,
+        #[serde(borrow)] Vec<Statement<'a>>,
+        #[serde(borrow)] Vec<Statement<'a>>,
+    ),
+    Range(
+        #[serde(borrow)] Box<Expression<'a>>,
+        #[serde(borrow)] Vec<Statement<'a>>,
+        Span,
+    ),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Statement<'a> {
-    Expression(Expression<'a>),
-    Block(&'a str, Vec<Statement<'a>>),
-    Define(&'a str, Option<&'a str>, Vec<Statement<'a>>),
-    Import(&'a str), // vars?
+    Expression(#[serde(borrow)] Expression<'a>),
+    Block(&'a str, #[serde(borrow)] Vec<Statement<'a>>, Span),
+    Define(
+        &'a str,
+        #[serde(borrow)] Option<&'a str>,
+        #[serde(borrow)] Vec<Statement<'a>>,
+        Span,
+    ),
+    Import(&'a str, Span), // vars?
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IfExpr<'a> {
+    #[serde(borrow)]
     left: Box<Expression<'a>>,
+    #[serde(borrow)]
     right: Option<Box<Expression<'a>>>,
     op: Option<Op>,
+    span: Span,
 }
 
 #[derive(Debug)]
@@ -30,7 +68,7 @@ pub enum BinOp {
     Or,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Op {
     Eq,
     Neq,
@@ -38,146 +76,429 @@ pub enum Op {
     Gte,
     Lt,
     Lte,
+    In,
+    Contains,
 }
 
-impl<'a> IfExpr<'a> {
-    pub fn eval(&self, ctx: &serde_json::Value) -> Result<bool, TemplusError> {
-        match &self.op {
-            Some(op) => match *self.left {
-                Expression::Variable(var_name) => {
-                    let left_value = ctx
-                        .get(var_name)
-                        .ok_or(TemplusError::DeafultError("unknown var in if".to_owned()))?;
-
-                    let right_expr = self
-                        .right
-                        .as_ref()
-                        .ok_or(TemplusError::DeafultError("missing right".to_string()))?;
-
-                    let rigth_value = match **right_expr {
-                        Expression::Variable(name) => ctx
-                            .get(name)
-                            .ok_or(TemplusError::DeafultError("".to_string()))?
-                            .clone(),
-                        Expression::Literal(lit) => {
-                            let mut result = serde_json::Value::String(lit.to_string());
-                            // is number
-                            if let Ok(num) = lit.parse::<i64>() {
-                                result = serde_json::Value::Number(serde_json::Number::from(num))
-                            }
-                            // is bool
-                            if lit == "true" || lit == "false" {
-                                result = serde_json::Value::Bool(lit.parse::<bool>().unwrap())
-                            }
-                            result
-                        }
-                        _ => return Err(TemplusError::DeafultError("".to_string())),
-                    };
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
 
-                    match left_value {
-                        serde_json::Value::Null => {
-                            Err(TemplusError::DeafultError("null".to_string()))
-                        }
-                        serde_json::Value::Bool(bool) => IfExpr::eval_bool(bool, op, &rigth_value),
-                        serde_json::Value::Number(num) => {
-                            IfExpr::eval_number(num.as_i64().unwrap(), op, &rigth_value)
-                        }
-                        serde_json::Value::String(string) => {
-                            IfExpr::eval_string(string, op, &rigth_value)
-                        }
-                        serde_json::Value::Array(_) => Ok(false),
-                        serde_json::Value::Object(_) => Ok(false),
-                    }
-                }
-                _ => {
-                    return Err(TemplusError::DeafultError(
-                        "if statements require a variable on the left".to_owned(),
-                    ))
-                }
-            },
-            None => match *self.left {
-                Expression::Variable(var) => match ctx.get(var) {
-                    Some(v) => match v {
-                        serde_json::Value::Null => Ok(false),
-                        serde_json::Value::Bool(_v) => Ok(_v.clone()),
-                        serde_json::Value::Number(_v) => {
-                            Ok(_v.as_i64().ok_or(TemplusError::DeafultError(
-                                "serde maria, what have you done?".to_owned(),
-                            ))? != 0)
-                        }
-                        serde_json::Value::String(_v) => Ok(_v.len() > 0),
-                        serde_json::Value::Array(_v) => Ok(_v.len() > 0),
-                        serde_json::Value::Object(_v) => Ok(true),
-                    },
-                    None => Ok(false),
+impl<'a> Expression<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Variable(_, span) => *span,
+            Expression::Literal(_, span) => *span,
+            Expression::Number(_, span) => *span,
+            Expression::Arithmetic(_, _, _, span) => *span,
+            Expression::Negate(_, span) => *span,
+            Expression::Safe(_, span) => *span,
+            Expression::If(if_expr, _, _) => if_expr.span,
+            Expression::Range(_, _, span) => *span,
+        }
+    }
+
+    /// evaluates vars, literals and arithmetic to a value
+    pub fn eval<'c, C: Context + ?Sized>(
+        &self,
+        ctx: &'c C,
+        undefined: UndefinedBehavior,
+    ) -> Result<Cow<'c, Value>, TemplusError> {
+        match self {
+            Expression::Variable(name, span) => resolve_var(ctx, name, *span, undefined),
+            Expression::Literal(lit, _) => Ok(Cow::Owned(match lit.parse::<bool>() {
+                Ok(bool) => Value::Bool(bool),
+                Err(_) => Value::String(lit.to_string()),
+            })),
+            Expression::Number(num, span) => {
+                Ok(Cow::Owned(Value::Number(parse_number(num).ok_or(
+                    TemplusError::RenderError((format!("invalid number literal '{}'", num), *span)),
+                )?)))
+            }
+            Expression::Arithmetic(left, op, right, span) => {
+                let left = left.eval(ctx, undefined)?;
+                let right = right.eval(ctx, undefined)?;
+                Ok(Cow::Owned(op.apply(&left, &right, *span)?))
+            }
+            Expression::Negate(expr, span) => {
+                let value = expr.eval(ctx, undefined)?;
+                Ok(Cow::Owned(negate(&value, *span)?))
+            }
+            Expression::Safe(expr, span) => match expr.eval(ctx, undefined)? {
+                value @ Cow::Borrowed(Value::SafeHtml(_)) => Ok(value),
+                value => match display_value(&value) {
+                    Some(html) => Ok(Cow::Owned(Value::SafeHtml(html.into_owned()))),
+                    None => Err(TemplusError::TypeError((
+                        format!("cannot mark {} as safe", type_name(&value)),
+                        *span,
+                    ))),
                 },
-                Expression::Literal(_) => Ok(true),
-                _ => {
-                    return Err(TemplusError::DeafultError(
-                        "wtf are you doing in you if statement".to_owned(),
-                    ))
-                }
             },
+            _ => Err(TemplusError::RenderError((
+                format!("{} is not a value", self),
+                self.span(),
+            ))),
+        }
+    }
+}
+
+impl ArithOp {
+    fn from_token(token: &Token<'_>) -> Option<Self> {
+        match token {
+            Token::Plus => Some(ArithOp::Add),
+            Token::Minus => Some(ArithOp::Sub),
+            Token::Star => Some(ArithOp::Mul),
+            Token::Slash => Some(ArithOp::Div),
+            Token::Percent => Some(ArithOp::Rem),
+            _ => None,
         }
     }
 
-    fn eval_number(left: i64, op: &Op, right: &serde_json::Value) -> Result<bool, TemplusError> {
-        let num = match right {
-            serde_json::Value::Number(num) => num.as_i64().unwrap(),
+    fn precedence(&self) -> u8 {
+        match self {
+            ArithOp::Add | ArithOp::Sub => 1,
+            ArithOp::Mul | ArithOp::Div | ArithOp::Rem => 2,
+        }
+    }
+
+    /// numbers do math, `+` with a string on either side concatenates, null stays null
+    pub fn apply(&self, left: &Value, right: &Value, span: Span) -> Result<Value, TemplusError> {
+        match (left, right) {
+            (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+            (Value::Number(l), Value::Number(r)) => {
+                self.apply_numbers(l, r, span).map(Value::Number)
+            }
+            (Value::String(_) | Value::SafeHtml(_), _)
+            | (_, Value::String(_) | Value::SafeHtml(_))
+                if *self == ArithOp::Add =>
+            {
+                match (display_value(left), display_value(right)) {
+                    (Some(l), Some(r)) => Ok(Value::String(l.into_owned() + &r)),
+                    _ => Err(self.type_error(left, right, span)),
+                }
+            }
+            _ => Err(self.type_error(left, right, span)),
+        }
+    }
+
+    fn apply_numbers(
+        &self,
+        left: &Number,
+        right: &Number,
+        span: Span,
+    ) -> Result<Number, TemplusError> {
+        let division_by_zero = || TemplusError::RenderError(("division by zero".to_owned(), span));
+
+        // ints stay exact as long as the result fits, otherwise we fall back to floats
+        if let (Some(l), Some(r)) = (as_int(left), as_int(right)) {
+            let result = match self {
+                ArithOp::Add => l.checked_add(r),
+                ArithOp::Sub => l.checked_sub(r),
+                ArithOp::Mul => l.checked_mul(r),
+                ArithOp::Div | ArithOp::Rem if r == 0 => return Err(division_by_zero()),
+                ArithOp::Div => l.checked_div(r),
+                ArithOp::Rem => l.checked_rem(r),
+            };
+            if let Some(num) = result.and_then(number_from_int) {
+                return Ok(num);
+            }
+        }
+
+        let (l, r) = match (left.as_f64(), right.as_f64()) {
+            (Some(l), Some(r)) => (l, r),
+            _ => return Err(self.type_error_numbers(span)),
+        };
+        let result = match self {
+            ArithOp::Add => l + r,
+            ArithOp::Sub => l - r,
+            ArithOp::Mul => l * r,
+            ArithOp::Div | ArithOp::Rem if r == 0.0 => return Err(division_by_zero()),
+            ArithOp::Div => l / r,
+            ArithOp::Rem => l % r,
+        };
+        Number::from_f64(result).ok_or(TemplusError::RenderError((
+            format!("result of '{}' is not a finite number", self),
+            span,
+        )))
+    }
+
+    fn type_error(&self, left: &Value, right: &Value, span: Span) -> TemplusError {
+        TemplusError::TypeError((
+            format!(
+                "cannot apply '{}' to {} and {}",
+                self,
+                type_name(left),
+                type_name(right)
+            ),
+            span,
+        ))
+    }
+
+    fn type_error_numbers(&self, span: Span) -> TemplusError {
+        TemplusError::TypeError((format!("cannot apply '{}' to these numbers", self), span))
+    }
+}
+
+impl std::fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithOp::Add => write!(f, "+"),
+            ArithOp::Sub => write!(f, "-"),
+            ArithOp::Mul => write!(f, "*"),
+            ArithOp::Div => write!(f, "/"),
+            ArithOp::Rem => write!(f, "%"),
+        }
+    }
+}
+
+impl<'a> Statement<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Expression(expr) => expr.span(),
+            Statement::Block(_, _, span) => *span,
+            Statement::Define(_, _, _, span) => *span,
+            Statement::Import(_, span) => *span,
+        }
+    }
+}
+
+impl<'a> IfExpr<'a> {
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn left(&self) -> &Expression<'a> {
+        &self.left
+    }
+
+    pub fn right(&self) -> Option<&Expression<'a>> {
+        self.right.as_deref()
+    }
+
+    pub fn op(&self) -> Option<&Op> {
+        self.op.as_ref()
+    }
+
+    pub fn eval<C: Context + ?Sized>(
+        &self,
+        ctx: &C,
+        undefined: UndefinedBehavior,
+    ) -> Result<bool, TemplusError> {
+        let left_value = self.left.eval(ctx, undefined)?;
+        let (op, right_expr) = match (&self.op, &self.right) {
+            (Some(op), Some(right)) => (op, right),
+            _ => return Ok(is_truthy(&left_value)),
+        };
+        let rigth_value = right_expr.eval(ctx, undefined)?;
+        IfExpr::compare(&left_value, op, &rigth_value, self.span)
+    }
+
+    /// applies a condition operator to two evaluated values
+    pub(crate) fn compare(
+        left_value: &Value,
+        op: &Op,
+        rigth_value: &Value,
+        span: Span,
+    ) -> Result<bool, TemplusError> {
+        // undefined vars resolve to null outside of strict mode
+        if left_value.is_null() || rigth_value.is_null() {
+            return Ok(IfExpr::eval_null(
+                op,
+                left_value.is_null() && rigth_value.is_null(),
+            ));
+        }
+
+        match op {
+            Op::In => IfExpr::eval_in(left_value, rigth_value)
+                .map_err(|msg| TemplusError::TypeError((msg, span))),
+            Op::Contains => IfExpr::eval_in(rigth_value, left_value)
+                .map_err(|msg| TemplusError::TypeError((msg, span))),
             _ => {
-                return Err(TemplusError::DeafultError(
-                    "camparing number to unknown".to_string(),
-                ))
+                let result = match left_value {
+                    Value::Bool(bool) => IfExpr::eval_bool(bool, op, rigth_value),
+                    Value::Number(num) => IfExpr::eval_number(num, op, rigth_value),
+                    Value::String(string) | Value::SafeHtml(string) => {
+                        IfExpr::eval_string(string, op, rigth_value)
+                    }
+                    _ => Ok(false),
+                };
+                result.map_err(|msg| TemplusError::RenderError((msg, span)))
             }
+        }
+    }
+
+    /// array elements, object keys and substrings
+    fn eval_in(needle: &Value, haystack: &Value) -> Result<bool, String> {
+        match (needle, haystack) {
+            (_, Value::Array(items)) => Ok(items.iter().any(|item| values_equal(needle, item))),
+            (Value::String(key), Value::Object(map)) => Ok(map.contains_key(key)),
+            (
+                Value::String(sub) | Value::SafeHtml(sub),
+                Value::String(string) | Value::SafeHtml(string),
+            ) => Ok(string.contains(sub.as_str())),
+            _ => Err(format!(
+                "cannot look for {} in {}",
+                type_name(needle),
+                type_name(haystack)
+            )),
+        }
+    }
+
+    fn eval_null(op: &Op, both_null: bool) -> bool {
+        match op {
+            Op::Eq => both_null,
+            Op::Neq => !both_null,
+            _ => false,
+        }
+    }
+
+    fn eval_number(left: &Number, op: &Op, right: &Value) -> Result<bool, String> {
+        let num = match right {
+            Value::Number(num) => num,
+            _ => return Err("camparing number to unknown".to_string()),
+        };
+        // NaN never makes it into a Number, but stay total anyway
+        let ordering = match compare_numbers(left, num) {
+            Some(ordering) => ordering,
+            None => return Ok(matches!(op, Op::Neq)),
         };
         match op {
-            Op::Eq => Ok(left == num),
-            Op::Neq => Ok(left != num),
-            Op::Gt => Ok(left > num),
-            Op::Gte => Ok(left >= num),
-            Op::Lt => Ok(left < num),
-            Op::Lte => Ok(left <= num),
+            Op::Eq => Ok(ordering.is_eq()),
+            Op::Neq => Ok(ordering.is_ne()),
+            Op::Gt => Ok(ordering.is_gt()),
+            Op::Gte => Ok(ordering.is_ge()),
+            Op::Lt => Ok(ordering.is_lt()),
+            Op::Lte => Ok(ordering.is_le()),
+            Op::In | Op::Contains => Err("membership is not a number compare".to_string()),
         }
     }
 
-    fn eval_bool(left: &bool, op: &Op, right: &serde_json::Value) -> Result<bool, TemplusError> {
+    fn eval_bool(left: &bool, op: &Op, right: &Value) -> Result<bool, String> {
         let bool = match right {
-            serde_json::Value::Bool(bool) => bool,
-            _ => {
-                return Err(TemplusError::DeafultError(
-                    "comparing bool with not bool".to_string(),
-                ))
-            }
+            Value::Bool(bool) => bool,
+            _ => return Err("comparing bool with not bool".to_string()),
         };
         match op {
-            Op::Eq => Ok(left == right),
-            Op::Neq => Ok(left != right),
-            _ => {
-                return Err(TemplusError::DeafultError(
-                    "bool campare can only be eq or neq".to_string(),
-                ))
-            }
+            Op::Eq => Ok(left == bool),
+            Op::Neq => Ok(left != bool),
+            _ => Err("bool campare can only be eq or neq".to_string()),
         }
     }
 
-    fn eval_string(left: &str, op: &Op, right: &serde_json::Value) -> Result<bool, TemplusError> {
+    /// strings compare lexically, byte by byte
+    fn eval_string(left: &str, op: &Op, right: &Value) -> Result<bool, String> {
         let string = match right {
-            serde_json::Value::String(string) => string,
-            _ => {
-                return Err(TemplusError::DeafultError(
-                    "comparing bool with not bool".to_string(),
-                ))
-            }
+            Value::String(string) | Value::SafeHtml(string) => string.as_str(),
+            _ => return Err("comparing string with not string".to_string()),
         };
         match op {
-            Op::Eq => Ok(left == right),
-            Op::Neq => Ok(left != right),
-            _ => {
-                return Err(TemplusError::DeafultError(
-                    "string campare can only be eq or neq".to_string(),
-                ))
-            }
+            Op::Eq => Ok(left == string),
+            Op::Neq => Ok(left != string),
+            Op::Gt => Ok(left > string),
+            Op::Gte => Ok(left >= string),
+            Op::Lt => Ok(left < string),
+            Op::Lte => Ok(left <= string),
+            Op::In | Op::Contains => Err("membership is not a string compare".to_string()),
+        }
+    }
+}
+
+/// parses a number literal, keeping integers exact and falling back to floats
+pub(crate) fn parse_number(lit: &str) -> Option<Number> {
+    if let Ok(num) = lit.parse::<i64>() {
+        return Some(num.into());
+    }
+    if let Ok(num) = lit.parse::<u64>() {
+        return Some(num.into());
+    }
+    lit.parse::<f64>().ok().and_then(Number::from_f64)
+}
+
+fn as_int(num: &Number) -> Option<i128> {
+    num.as_i64()
+        .map(i128::from)
+        .or_else(|| num.as_u64().map(i128::from))
+}
+
+fn number_from_int(num: i128) -> Option<Number> {
+    if let Ok(num) = i64::try_from(num) {
+        return Some(num.into());
+    }
+    u64::try_from(num).ok().map(Number::from)
+}
+
+/// compares ints exactly, including u64 values above i64::MAX, anything else as floats
+fn compare_numbers(left: &Number, right: &Number) -> Option<std::cmp::Ordering> {
+    match (as_int(left), as_int(right)) {
+        (Some(l), Some(r)) => Some(l.cmp(&r)),
+        _ => left.as_f64()?.partial_cmp(&right.as_f64()?),
+    }
+}
+
+/// resolves a var in conditions, undefined vars are null unless we are strict
+fn resolve_var<'c, C: Context + ?Sized>(
+    ctx: &'c C,
+    name: &str,
+    span: Span,
+    undefined: UndefinedBehavior,
+) -> Result<Cow<'c, Value>, TemplusError> {
+    match (lookup_var(ctx, name, span)?, undefined) {
+        (Some(value), _) => Ok(value),
+        (None, UndefinedBehavior::Strict) => Err(TemplusError::RenderError((
+            format!("var '{}' not found", name),
+            span,
+        ))),
+        (None, _) => Ok(Cow::Borrowed(&NULL)),
+    }
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => {
+            compare_numbers(l, r).is_some_and(|ordering| ordering.is_eq())
         }
+        (Value::String(l) | Value::SafeHtml(l), Value::String(r) | Value::SafeHtml(r)) => l == r,
+        _ => left == right,
+    }
+}
+
+/// `-value`, null stays null
+pub(crate) fn negate(value: &Value, span: Span) -> Result<Value, TemplusError> {
+    match value {
+        Value::Null => Ok(Value::Null),
+        Value::Number(num) => ArithOp::Sub.apply(&Value::from(0), &Value::Number(*num), span),
+        value => Err(TemplusError::TypeError((
+            format!("cannot negate {}", type_name(value)),
+            span,
+        ))),
+    }
+}
+
+pub(crate) fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(bool) => *bool,
+        Value::Number(num) => num.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(string) | Value::SafeHtml(string) => !string.is_empty(),
+        Value::Array(array) => !array.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::SafeHtml(_) => "safe html",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
     }
 }
 
@@ -185,21 +506,21 @@ impl<'a> std::fmt::Display for Statement<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Statement::Expression(expr) => write!(f, "({})", expr),
-            Statement::Block(name, statements) => {
+            Statement::Block(name, statements, _) => {
                 write!(f, "(block:{})", name)?;
                 for stat in statements {
                     write!(f, "{}", stat)?;
                 }
-                write!(f, "\n")
+                writeln!(f)
             }
-            Statement::Define(name, _, statements) => {
+            Statement::Define(name, _, statements, _) => {
                 write!(f, "(define:{})", name)?;
                 for stat in statements {
                     write!(f, "{}", stat)?;
                 }
-                write!(f, "\n")
+                writeln!(f)
             }
-            Statement::Import(name) => write!(f, "(import:{})", name),
+            Statement::Import(name, _) => write!(f, "(import:{})", name),
         }
     }
 }
@@ -207,58 +528,102 @@ impl<'a> std::fmt::Display for Statement<'a> {
 impl<'a> std::fmt::Display for Expression<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Expression::Variable(_) => write!(f, "[var]"),
-            Expression::Literal(_) => write!(f, "[lit]"),
+            Expression::Variable(_, _) => write!(f, "[var]"),
+            Expression::Literal(_, _) => write!(f, "[lit]"),
+            Expression::Number(_, _) => write!(f, "[num]"),
+            Expression::Arithmetic(_, op, _, _) => write!(f, "[{}]", op),
+            Expression::Negate(_, _) => write!(f, "[neg]"),
+            Expression::Safe(_, _) => write!(f, "[safe]"),
             Expression::If(_, _, _) => write!(f, "[if]"),
-            Expression::Range(_, _) => write!(f, "[range]"),
+            Expression::Range(_, _, _) => write!(f, "[range]"),
         }
     }
 }
 
 impl<'a> std::fmt::Display for IfExpr<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} {:?} {:?}\n", self.left, self.op, self.right)
+        writeln!(f, "{:?} {:?} {:?}", self.left, self.op, self.right)
     }
 }
 
 // ---------------------------------------------
+/// how deep blocks and expressions may nest before parsing gives up,
+/// keeps hostile templates from overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+/// how a run of statements was closed
+enum Close {
+    End(Span),
+    Else(Span),
+    Eof,
+}
+
 pub struct Parser<'a> {
-    lexer: Lexer<'a>,
+    lexer: std::iter::Peekable<Lexer<'a>>,
+    depth: usize,
+    /// constructs waiting for their `end`, innermost last
+    open: Vec<(&'static str, Span)>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(code: &'a [u8]) -> Self {
         Self {
-            lexer: Lexer::new(code),
+            lexer: Lexer::new(code).peekable(),
+            depth: 0,
+            open: vec![],
         }
     }
 
-    /// big juicy recursive func
+    /// fails on delimiters the lexer cannot work with, see `Syntax::validate`
+    pub fn with_syntax(code: &'a [u8], syntax: &Syntax) -> Result<Self, TemplusError> {
+        Ok(Self {
+            lexer: Lexer::with_syntax(code, syntax.clone())?.peekable(),
+            depth: 0,
+            open: vec![],
+        })
+    }
+
     pub fn parse(&mut self) -> Result<Vec<Statement<'a>>, TemplusError> {
+        self.open.clear();
         let mut out = vec![];
+        match self.parse_into(&mut out)? {
+            Close::Eof => Ok(out),
+            close => Err(unexpected(close)),
+        }
+    }
+
+    /// big juicy recursive func, parses up to the next `end`, `else` or the end of the
+    /// template into `out`
+    fn parse_into(&mut self, out: &mut Vec<Statement<'a>>) -> Result<Close, TemplusError> {
         while let Some(token_result) = self.lexer.next() {
             let (token, span) = token_result?;
             match token {
                 Token::CodeStart => (), // don't care
                 Token::CodeEnd => (),   // don't care
                 Token::Block => {
+                    self.open.push(("block", span));
                     let name = match self.lexer.next() {
                         Some(Ok((Token::Literal(name), _))) => name,
                         Some(Err(err)) => return Err(err),
                         _ => return Err(TemplusError::ParserError(span)),
                     };
-                    let statement = Statement::Block(name, self.parse()?);
+                    let statement = Statement::Block(name, self.parse_body("block", span)?, span);
                     out.push(statement);
                 }
                 Token::Template(template) => {
-                    out.push(Statement::Expression(Expression::Literal(template)));
+                    out.push(Statement::Expression(Expression::Literal(template, span)));
                 }
-                Token::Literal(literal) => {}
-                Token::Var(var) => {
-                    let statement = Statement::Expression(Expression::Variable(var));
-                    out.push(statement);
+                Token::Var(_)
+                | Token::Literal(_)
+                | Token::Number(_)
+                | Token::Minus
+                | Token::LParen => {
+                    let operand = self.parse_operand(token, span)?;
+                    let expr = self.parse_arithmetic(operand, 0)?;
+                    out.push(Statement::Expression(self.parse_filters(expr)?));
                 }
                 Token::Define => {
+                    self.open.push(("define", span));
                     let name = match self.lexer.next() {
                         Some(Ok((Token::Literal(name), _))) => name,
                         Some(Err(err)) => return Err(err),
@@ -267,17 +632,27 @@ impl<'a> Parser<'a> {
 
                     match self.lexer.next() {
                         Some(Ok((Token::Extends, _))) => {
-                            match self.lexer.next() {
-                                Some(Ok((Token::Literal(extends), _))) => {
-                                    let statement =
-                                        Statement::Define(name, Some(extends), self.parse()?);
-                                    out.push(statement);
-                                }
-                                _ => {}
+                            let extends = match self.lexer.next() {
+                                Some(Ok((Token::Literal(extends), _))) => extends,
+                                Some(Err(err)) => return Err(err),
+                                _ => return Err(TemplusError::ParserError(span)),
                             };
+                            let statement = Statement::Define(
+                                name,
+                                Some(extends),
+                                self.parse_body("define", span)?,
+                                span,
+                            );
+                            out.push(statement);
                         }
+                        Some(Err(err)) => return Err(err),
                         _ => {
-                            let statement = Statement::Define(name, None, self.parse()?);
+                            let statement = Statement::Define(
+                                name,
+                                None,
+                                self.parse_body("define", span)?,
+                                span,
+                            );
                             out.push(statement);
                         }
                     }
@@ -288,13 +663,15 @@ impl<'a> Parser<'a> {
                         Some(Err(err)) => return Err(err),
                         _ => return Err(TemplusError::ParserError(span)),
                     };
-                    let statement = Statement::Import(name);
+                    let statement = Statement::Import(name, span);
                     out.push(statement);
                 }
                 Token::Range => {
+                    self.open.push(("range", span));
                     let var = match self.lexer.next() {
-                        Some(Ok((Token::Var(var), _))) => Expression::Variable(var),
-                        Some(Ok((Token::Literal(lit), _))) => Expression::Literal(lit),
+                        Some(Ok((Token::Var(var), at))) => Expression::Variable(var, at),
+                        Some(Ok((Token::Literal(lit), at))) => Expression::Literal(lit, at),
+                        Some(Ok((Token::Number(num), at))) => Expression::Number(num, at),
                         _ => {
                             return Err(TemplusError::SyntaxError((
                                 "expected var".to_owned(),
@@ -302,96 +679,314 @@ impl<'a> Parser<'a> {
                             )))
                         }
                     };
-                    let statement =
-                        Statement::Expression(Expression::Range(Box::new(var), self.parse()?));
+                    let statement = Statement::Expression(Expression::Range(
+                        Box::new(var),
+                        self.parse_body("range", span)?,
+                        span,
+                    ));
                     out.push(statement);
                 }
                 Token::If => {
-                    let left = match self.lexer.next() {
-                        Some(Ok((Token::Literal(name), _))) => Expression::Literal(name),
-                        Some(Ok((Token::Var(name), at))) => Expression::Variable(name),
-                        Some(Err(err)) => return Err(err),
-                        _ => return Err(TemplusError::ParserError(span)),
-                    };
+                    self.open.push(("if", span));
+                    let left = self.parse_expression(span)?;
 
-                    match self.lexer.next() {
-                        Some(Ok((Token::CodeEnd, _))) => {
-                            let mut if_body = self.parse()?;
-                            let (btrue, bfalse): (Vec<Statement<'_>>, Vec<Statement<'_>>) =
-                                match if_body.len() > 1 {
-                                    true => {
-                                        (if_body.drain(..1).collect(), if_body.drain(..).collect())
-                                    }
-                                    false => (if_body, vec![]),
-                                };
-
-                            out.push(Statement::Expression(Expression::If(
-                                IfExpr {
-                                    left: Box::new(left),
-                                    right: None,
-                                    op: None,
-                                },
-                                btrue,
-                                bfalse,
-                            )))
-                        }
-                        _op => {
+                    let (right, op) = match self.next_token(span)? {
+                        (Token::CodeEnd, _) => (None, None),
+                        (_op, at) => {
                             let op = match _op {
-                                Some(Ok((Token::Eq, at))) => Op::Eq,
-                                Some(Ok((Token::Neq, at))) => Op::Neq,
-                                Some(Ok((Token::Gt, at))) => Op::Gt,
-                                Some(Ok((Token::Gte, at))) => Op::Gte,
-                                Some(Ok((Token::Lt, at))) => Op::Lt,
-                                Some(Ok((Token::Lte, at))) => Op::Lte,
-                                Some(Err(err)) => return Err(err),
+                                Token::Eq => Op::Eq,
+                                Token::Neq => Op::Neq,
+                                Token::Gt => Op::Gt,
+                                Token::Gte => Op::Gte,
+                                Token::Lt => Op::Lt,
+                                Token::Lte => Op::Lte,
+                                Token::In => Op::In,
+                                Token::Contains => Op::Contains,
                                 _t => {
-                                    return Err(TemplusError::DeafultError(format!(
-                                        "error with {:?}",
-                                        _t
+                                    return Err(TemplusError::SyntaxError((
+                                        format!("expected comparison operator, found {:?}", _t),
+                                        at,
                                     )))
                                 }
                             };
+                            (Some(Box::new(self.parse_expression(span)?)), Some(op))
+                        }
+                    };
 
-                            let right = match self.lexer.next() {
-                                Some(Ok((Token::Literal(name), _))) => Expression::Literal(name),
-                                Some(Ok((Token::Var(name), _))) => Expression::Variable(name),
-                                Some(Err(err)) => return Err(err),
-                                _ => return Err(TemplusError::ParserError(span)),
-                            };
+                    let (btrue, bfalse) = match self.parse_until_close("if", span)? {
+                        (btrue, Close::Else(_)) => (btrue, self.parse_body("if", span)?),
+                        (btrue, _) => (btrue, vec![]),
+                    };
 
+                    out.push(Statement::Expression(Expression::If(
+                        IfExpr {
+                            left: Box::new(left),
+                            right,
+                            op,
+                            span,
+                        },
+                        btrue,
+                        bfalse,
+                    )))
+                }
+                // the lexer hands us the raw content as plain template text
+                Token::Raw => {
+                    self.open.push(("raw", span));
+                    out.extend(self.parse_body("raw", span)?)
+                }
+                Token::Else => return Ok(Close::Else(span)),
+                Token::End => return Ok(Close::End(span)),
+                _any => {
+                    return Err(TemplusError::SyntaxError((
+                        format!("this token is not supposed to be here: {:?}", _any),
+                        span,
+                    )))
+                }
+            }
+        }
+        Ok(Close::Eof)
+    }
 
-                            let mut if_body = self.parse()?;
-                            let (btrue, bfalse): (Vec<Statement<'_>>, Vec<Statement<'_>>) =
-                                match if_body.len() > 1 {
-                                    true => {
-                                        (if_body.drain(..1).collect(), if_body.drain(..).collect())
-                                    }
-                                    false => (if_body, vec![]),
-                                };
-
-                            out.push(Statement::Expression(Expression::If(
-                                IfExpr {
-                                    left: Box::new(left),
-                                    right: Some(Box::new(right)),
-                                    op: Some(op),
-                                },
-                                btrue,
-                                bfalse,
-                            )))
-                        }
+    /// parses the body of `construct` up to its `end`
+    fn parse_body(
+        &mut self,
+        construct: &'static str,
+        span: Span,
+    ) -> Result<Vec<Statement<'a>>, TemplusError> {
+        match self.parse_until_close(construct, span)? {
+            (body, Close::End(_)) => Ok(body),
+            (_, close) => Err(unexpected(close)),
+        }
+    }
+
+    /// parses the body of `construct` up to its `end` or `else`
+    fn parse_until_close(
+        &mut self,
+        construct: &'static str,
+        span: Span,
+    ) -> Result<(Vec<Statement<'a>>, Close), TemplusError> {
+        // constructs are opened at their keyword and stay open on errors, so recovery
+        // knows how many `end`s to skip
+        let mut body = vec![];
+        match self.nested(span, |parser| parser.parse_into(&mut body))? {
+            Close::Eof => {
+                self.open.pop();
+                Err(unclosed(construct, span))
+            }
+            close => {
+                self.open.pop();
+                Ok((body, close))
+            }
+        }
+    }
+
+    /// parses the whole template collecting every error instead of stopping at the first,
+    /// after an error parsing picks up again at the next `{{ end }}` or `{{ define }}`
+    pub fn parse_recovering(&mut self) -> (Vec<Statement<'a>>, Vec<TemplusError>) {
+        self.open.clear();
+        let mut out = vec![];
+        let mut errors = vec![];
+        while self.lexer.peek().is_some() {
+            match self.parse_into(&mut out) {
+                Ok(Close::Eof) => (),
+                Ok(close) => errors.push(unexpected(close)),
+                Err(err) => {
+                    errors.push(err);
+                    self.synchronize(&mut errors);
+                }
+            }
+        }
+        // whatever recovery could not close is still open at the end of the template
+        while let Some((construct, span)) = self.open.pop() {
+            errors.push(unclosed(construct, span));
+        }
+        (out, errors)
+    }
+
+    /// drops tokens up to the `end` of every construct still open, or up to the next
+    /// `define`, lexer errors on the way are still reported
+    fn synchronize(&mut self, errors: &mut Vec<TemplusError>) {
+        while let Some(result) = self.lexer.peek() {
+            if self.open.is_empty() {
+                return;
+            }
+            match result {
+                Ok((Token::End, _)) => {
+                    self.lexer.next();
+                    self.open.pop();
+                }
+                Ok((Token::Define, _)) => {
+                    self.open.clear();
+                    return;
+                }
+                Ok(_) => {
+                    self.lexer.next();
+                }
+                Err(_) => {
+                    if let Some(Err(err)) = self.lexer.next() {
+                        errors.push(err);
                     }
                 }
-                Token::Else => (),
-                Token::End => return Ok(out),
-                _any => {
-                    return Err(TemplusError::DeafultError(format!(
-                        "this token is supposed to be be here: {:?}",
-                        _any
+            }
+        }
+    }
+
+    fn nested<T>(
+        &mut self,
+        span: Span,
+        parse: impl FnOnce(&mut Self) -> Result<T, TemplusError>,
+    ) -> Result<T, TemplusError> {
+        self.descend(span)?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn descend(&mut self, span: Span) -> Result<(), TemplusError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(TemplusError::SyntaxError((
+                format!("template nests deeper than {} levels", MAX_DEPTH),
+                span,
+            )));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn next_token(&mut self, span: Span) -> Result<(Token<'a>, Span), TemplusError> {
+        match self.lexer.next() {
+            Some(result) => result,
+            None => Err(TemplusError::SyntaxError((
+                "unexpected end of template".to_owned(),
+                span,
+            ))),
+        }
+    }
+
+    fn parse_expression(&mut self, span: Span) -> Result<Expression<'a>, TemplusError> {
+        let (token, at) = self.next_token(span)?;
+        let operand = self.parse_operand(token, at)?;
+        self.parse_arithmetic(operand, 0)
+    }
+
+    fn parse_operand(
+        &mut self,
+        token: Token<'a>,
+        span: Span,
+    ) -> Result<Expression<'a>, TemplusError> {
+        match token {
+            Token::Var(name) => Ok(Expression::Variable(name, span)),
+            Token::Literal(lit) => Ok(Expression::Literal(lit, span)),
+            Token::Number(num) => Ok(Expression::Number(num, span)),
+            Token::Minus => {
+                let (token, at) = self.next_token(span)?;
+                let operand = self.nested(span, |parser| parser.parse_operand(token, at))?;
+                Ok(Expression::Negate(Box::new(operand), span))
+            }
+            Token::LParen => {
+                let inner = self.nested(span, |parser| parser.parse_expression(span))?;
+                match self.next_token(span)? {
+                    (Token::RParen, _) => Ok(inner),
+                    (token, at) => Err(TemplusError::SyntaxError((
+                        format!("expected ')', found {:?}", token),
+                        at,
+                    ))),
+                }
+            }
+            token => Err(TemplusError::SyntaxError((
+                format!("expected a value, found {:?}", token),
+                span,
+            ))),
+        }
+    }
+
+    /// `| safe` after a value that gets printed
+    fn parse_filters(&mut self, expr: Expression<'a>) -> Result<Expression<'a>, TemplusError> {
+        let depth = self.depth;
+        let result = self.parse_filter_chain(expr);
+        self.depth = depth;
+        result
+    }
+
+    /// each filter wraps the value once more, so chains count against the depth limit
+    fn parse_filter_chain(
+        &mut self,
+        mut expr: Expression<'a>,
+    ) -> Result<Expression<'a>, TemplusError> {
+        while let Some(Ok((Token::Pipe, span))) = self.lexer.peek() {
+            let span = *span;
+            self.lexer.next();
+            self.descend(span)?;
+            expr = match self.next_token(span)? {
+                (Token::Safe, at) => Expression::Safe(Box::new(expr), at),
+                (token, at) => {
+                    return Err(TemplusError::SyntaxError((
+                        format!("expected a filter after `|`, found {:?}", token),
+                        at,
                     )))
                 }
+            };
+        }
+        Ok(expr)
+    }
+
+    /// precedence climbing over `+ - * / %`
+    fn parse_arithmetic(
+        &mut self,
+        left: Expression<'a>,
+        min_precedence: u8,
+    ) -> Result<Expression<'a>, TemplusError> {
+        let depth = self.depth;
+        let result = self.parse_chain(left, min_precedence);
+        self.depth = depth;
+        result
+    }
+
+    /// every operator in a chain nests the tree one level deeper, so it counts
+    /// against the depth limit like parens do
+    fn parse_chain(
+        &mut self,
+        mut left: Expression<'a>,
+        min_precedence: u8,
+    ) -> Result<Expression<'a>, TemplusError> {
+        loop {
+            let (op, span) = match self.lexer.peek() {
+                Some(Ok((token, span))) => match ArithOp::from_token(token) {
+                    Some(op) if op.precedence() >= min_precedence => (op, *span),
+                    _ => return Ok(left),
+                },
+                _ => return Ok(left),
+            };
+            self.lexer.next();
+            self.descend(span)?;
+
+            let (token, at) = self.next_token(span)?;
+            let mut right = self.parse_operand(token, at)?;
+            // let tighter binding operators on the right grab their operands first
+            while let Some(Ok((token, _))) = self.lexer.peek() {
+                match ArithOp::from_token(token) {
+                    Some(next) if next.precedence() > op.precedence() => {
+                        right = self.parse_arithmetic(right, next.precedence())?;
+                    }
+                    _ => break,
+                }
             }
+            left = Expression::Arithmetic(Box::new(left), op, Box::new(right), span);
+        }
+    }
+}
+
+fn unclosed(construct: &str, span: Span) -> TemplusError {
+    TemplusError::SyntaxError((format!("unclosed `{}` opened at {}", construct, span), span))
+}
+
+fn unexpected(close: Close) -> TemplusError {
+    match close {
+        Close::End(span) => TemplusError::SyntaxError(("unexpected `end`".to_owned(), span)),
+        Close::Else(span) => TemplusError::SyntaxError(("unexpected `else`".to_owned(), span)),
+        Close::Eof => {
+            TemplusError::SyntaxError(("unexpected end of template".to_owned(), Span::default()))
         }
-        Ok(out)
     }
 }
 
@@ -408,4 +1003,140 @@ mod tests {
             println!("template: {:?}", template);
         }
     }
+
+    #[test]
+    fn test_parser_depth_limit() {
+        let tmpl = "{{ if .a }}".repeat(MAX_DEPTH + 1);
+        assert!(matches!(
+            Parser::new(tmpl.as_bytes()).parse(),
+            Err(TemplusError::SyntaxError(_))
+        ));
+        let tmpl = format!("{{{{ {}1 }}}}", "(".repeat(100_000));
+        assert!(Parser::new(tmpl.as_bytes()).parse().is_err());
+
+        for op in ["+", "*", "- -"] {
+            let tmpl = format!("{{{{ 1{} }}}}", format!(" {} 1", op).repeat(100_000));
+            assert!(matches!(
+                Parser::new(tmpl.as_bytes()).parse(),
+                Err(TemplusError::SyntaxError(_))
+            ));
+        }
+        let tmpl = format!("{{{{ 1{} }}}}", " + 1".repeat(MAX_DEPTH - 1));
+        assert!(Parser::new(tmpl.as_bytes()).parse().is_ok());
+        let tmpl = format!("{{{{ .a{} }}}}", " | safe".repeat(100_000));
+        assert!(Parser::new(tmpl.as_bytes()).parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let tmpl = "{{ define 'a' }}{{ if }}{{ end }}<p>{{ end }}\n\
+                    {{ define 'b' }}{{ block }}{{ end }}{{ end }}\n\
+                    {{ define 'c' }}<p>{{ .a + }}</p>{{ end }}\n\
+                    {{ define 'd' }}<p>{{ .ok }}</p>{{ end }}";
+        let (statements, errors) = Parser::new(tmpl.as_bytes()).parse_recovering();
+        let spans = errors
+            .iter()
+            .map(|err| err.span().map(|span| (span.line(), span.column())))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec![Some((1, 23)), Some((2, 20)), Some((3, 28))]);
+        assert!(statements
+            .iter()
+            .any(|stmt| matches!(stmt, Statement::Define("d", _, _, _))));
+
+        let (_, errors) =
+            Parser::new(b"{{ # }}<p>{{ .a ~ }}</p>{{ define 'd' }}{{ end }}").parse_recovering();
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|err| matches!(err, TemplusError::LexerError(_))));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn parse_never_panics(code in proptest::collection::vec(proptest::num::u8::ANY, 0..256)) {
+            let _ = Parser::new(&code).parse();
+            let _ = Parser::new(&code).parse_recovering();
+        }
+
+        #[test]
+        fn parse_template_like_never_panics(
+            code in r#"([a-zä \t\n<>/.'"0-9()+*%=!&|-]|\{\{-? |\{\{|-?\}\}|/\*|\*/|if |else|end|range|raw|define|block|extends|import|set)*"#
+        ) {
+            let _ = Parser::new(code.as_bytes()).parse();
+        }
+    }
+
+    fn syntax_error(code: &str) -> String {
+        match Parser::new(code.as_bytes()).parse() {
+            Err(TemplusError::SyntaxError((msg, _))) => msg,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unclosed_and_stray_end() {
+        assert_eq!(
+            syntax_error("{{ define 'a' }}\n    {{ if .a }}<p></p>{{ end }}"),
+            "unclosed `define` opened at 1:4"
+        );
+        assert_eq!(
+            syntax_error("{{ define 'a' }}\n    {{ if .a }}<p></p>"),
+            "unclosed `if` opened at 2:8"
+        );
+        assert_eq!(
+            syntax_error("{{ define 'a' }}{{ range .a }}{{ end }}"),
+            "unclosed `define` opened at 1:4"
+        );
+        assert_eq!(
+            syntax_error("{{ define 'a' }}{{ end }}{{ end }}"),
+            "unexpected `end`"
+        );
+        assert_eq!(
+            syntax_error("{{ define 'a' }}{{ block 'b' }}{{ else }}{{ end }}{{ end }}"),
+            "unexpected `else`"
+        );
+        assert_eq!(
+            syntax_error("{{ define 'a' }}{{ if .a }}{{ else }}{{ else }}{{ end }}{{ end }}"),
+            "unexpected `else`"
+        );
+
+        let (_, errors) = Parser::new(b"{{ define 'a' }}{{ if .a }}{{ .b + }}").parse_recovering();
+        let messages = errors.iter().map(|err| err.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "expected a value, found CodeEnd ,at:1:36",
+                "unclosed `if` opened at 1:20 ,at:1:20",
+                "unclosed `define` opened at 1:4 ,at:1:4",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_safe_filter() {
+        let tmpl = "{{ define 'a' }}{{ .bio | safe }}{{ .a + 1 | safe }}{{ end }}";
+        let statements = Parser::new(tmpl.as_bytes()).parse().unwrap();
+        assert_eq!(
+            statements[0].to_string().trim_end(),
+            "(define:a)([safe])([safe])"
+        );
+        assert!(syntax_error("{{ .bio | }}").contains("filter"));
+        assert!(syntax_error("{{ .bio | 'x' }}").contains("filter"));
+    }
+
+    #[test]
+    fn test_if_else_branches() {
+        let tmpl = "{{ define 'a' }}{{ if .a }}x {{ .b }} y{{ else }}{{ .c }} z{{ end }}{{ end }}";
+        let statements = Parser::new(tmpl.as_bytes()).parse().unwrap();
+        match &statements[..] {
+            [Statement::Define(_, _, body, _)] => match &body[..] {
+                [Statement::Expression(Expression::If(_, btrue, bfalse))] => {
+                    assert_eq!(btrue.len(), 3);
+                    assert_eq!(bfalse.len(), 2);
+                }
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+    }
 }
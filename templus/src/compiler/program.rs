@@ -0,0 +1,117 @@
+use super::{
+    lexer::Span,
+    parser::{Expression, IfExpr},
+};
+use crate::renderer::lookup;
+use crate::value::Value;
+
+/// A single step of a flattened template. Jump targets are indices into the
+/// instruction list of the same template.
+#[derive(Debug, Clone)]
+pub(crate) enum Instr<'a> {
+    /// html and number literals, written as is
+    Emit(&'a str),
+    /// looks up a var and writes it escaped
+    Print(&'a str, Span),
+    /// arithmetic, negation and `| safe`
+    Eval(Expression<'a>, Span),
+    JumpIfFalse(IfExpr<'a>, usize),
+    Jump(usize),
+    /// starts a range over an array var, jumps to the target if there is nothing to range over
+    IterVar(&'a str, Span, usize),
+    /// starts a range over a number, jumps to the target if it is not positive
+    IterNum(i64, usize),
+    /// ends a range body, jumps back to the target while items are left
+    Next(usize),
+    /// a range that can only fail, reported once it is reached
+    Fail(&'static str, Span),
+}
+
+/// the array a range walks over
+pub(crate) enum Items<'c> {
+    Borrowed(&'c [Value]),
+    /// built by the context just for this range
+    Owned(Vec<Value>),
+    /// an array on the current item of an outer range, looked up again when needed
+    /// so it is never copied out of the items that range owns
+    Field(usize, &'c str),
+}
+
+/// state of a range that is being rendered
+pub(crate) enum Loop<'c> {
+    /// the items and the index of the current one, which is the context inside the body
+    Items(Items<'c>, usize),
+    Times(i64),
+}
+
+/// ranges that are being rendered, innermost last
+#[derive(Default)]
+pub(crate) struct Loops<'c>(Vec<Loop<'c>>);
+
+impl<'c> Loops<'c> {
+    pub fn push(&mut self, range: Loop<'c>) {
+        self.0.push(range)
+    }
+
+    /// moves the innermost range on, drops it once it is done
+    pub fn next(&mut self) -> bool {
+        let more = match self.0.last() {
+            Some(Loop::Items(items, i)) => i + 1 < self.array(items).map_or(0, <[Value]>::len),
+            Some(Loop::Times(left)) => *left > 1,
+            None => false,
+        };
+        match (more, self.0.last_mut()) {
+            (true, Some(Loop::Items(_, i))) => *i += 1,
+            (true, Some(Loop::Times(left))) => *left -= 1,
+            _ => _ = self.0.pop(),
+        }
+        more
+    }
+
+    /// the array behind `items`, `None` if a field no longer points at one
+    pub fn array<'l>(&'l self, items: &'l Items<'c>) -> Option<&'l [Value]> {
+        match items {
+            Items::Borrowed(items) => Some(items),
+            Items::Owned(items) => Some(items),
+            Items::Field(range, path) => match lookup(self.item(*range)?, path)? {
+                Value::Array(items) => Some(items),
+                _ => None,
+            },
+        }
+    }
+
+    /// the current item of the range at `range`
+    pub fn item(&self, range: usize) -> Option<&Value> {
+        match &self.0[range] {
+            Loop::Items(items, i) => self.array(items)?.get(*i),
+            Loop::Times(_) => None,
+        }
+    }
+
+    /// the current item of the range at `range`, if it is borrowed from the context
+    pub fn borrowed_item(&self, range: usize) -> Option<&'c Value> {
+        match &self.0[range] {
+            Loop::Items(Items::Borrowed(items), i) => items.get(*i),
+            _ => None,
+        }
+    }
+
+    /// the innermost range over items and its current item, the context inside the body
+    pub fn current(&self) -> Option<(usize, &Value)> {
+        (0..self.0.len())
+            .rev()
+            .find_map(|range| Some((range, self.item(range)?)))
+    }
+}
+
+/// points the jump at `at` to the end of the instructions emitted so far
+pub(crate) fn patch(instrs: &mut [Instr<'_>], at: usize) {
+    let target = instrs.len();
+    match &mut instrs[at] {
+        Instr::JumpIfFalse(_, to)
+        | Instr::Jump(to)
+        | Instr::IterVar(_, _, to)
+        | Instr::IterNum(_, to) => *to = target,
+        _ => {}
+    }
+}
@@ -2,7 +2,9 @@
 pub(crate) enum Token<'a> {
     Template(&'a str),
     Literal(&'a str),
+    Number(&'a str),
     Var(&'a str),
+    Comment(&'a str),
 
     Define,
     Extends,
@@ -13,6 +15,10 @@ pub(crate) enum Token<'a> {
     Else,
     End,
     Set,
+    In,
+    Contains,
+    Raw,
+    Safe,
 
     Eq,         // ==
     Neq,        // !=
@@ -22,12 +28,28 @@ pub(crate) enum Token<'a> {
     Lt,         // <
     And,        // &&
     Or,         // ||
+    Pipe,       // |
     Assign,     // =
+    Plus,       // +
+    Minus,      // -
+    Star,       // *
+    Slash,      // /
+    Percent,    // %
+    LParen,     // (
+    RParen,     // )
     CodeStart, // {{
     CodeEnd,   // }}
 }
 
 impl<'a> Token<'a> {
+    /// tokens after which a `-` is a binary minus rather than a negative number
+    pub fn is_operand(&self) -> bool {
+        matches!(
+            self,
+            Token::Literal(_) | Token::Number(_) | Token::Var(_) | Token::RParen
+        )
+    }
+
     pub fn try_from_bslice(bslice: &'a [u8]) -> Option<Self> {
         match bslice {
             b"define" => Some(Token::Define),
@@ -39,6 +61,10 @@ impl<'a> Token<'a> {
             b"if" => Some(Token::If),
             b"end" => Some(Token::End),
             b"set" => Some(Token::Set),
+            b"in" => Some(Token::In),
+            b"contains" => Some(Token::Contains),
+            b"raw" => Some(Token::Raw),
+            b"safe" => Some(Token::Safe),
             b"=" => Some(Token::Assign),
             b"==" => Some(Token::Eq),
             b"!=" => Some(Token::Neq),
@@ -48,6 +74,14 @@ impl<'a> Token<'a> {
             b"<" => Some(Token::Lt),
             b"&&" => Some(Token::And),
             b"||" => Some(Token::Or),
+            b"|" => Some(Token::Pipe),
+            b"+" => Some(Token::Plus),
+            b"-" => Some(Token::Minus),
+            b"*" => Some(Token::Star),
+            b"/" => Some(Token::Slash),
+            b"%" => Some(Token::Percent),
+            b"(" => Some(Token::LParen),
+            b")" => Some(Token::RParen),
             _ => None,
         }
     }
@@ -0,0 +1,656 @@
+use std::{borrow::Cow, fmt};
+
+use serde::{
+    ser::{self, Impossible},
+    Serialize,
+};
+
+use crate::{
+    compiler::{error::TemplusError, lexer::Span},
+    renderer::lookup,
+    value::{self, Value, ValueSerializer},
+};
+
+/// Anything templates can look their vars up in.
+pub trait Context {
+    /// the value at a dotted path like `user.name`, an empty path is the context itself.
+    /// Fails if the value is there but cannot be converted.
+    fn lookup(&self, path: &str) -> Result<Option<Cow<'_, Value>>, value::Error>;
+}
+
+impl Context for Value {
+    fn lookup(&self, path: &str) -> Result<Option<Cow<'_, Value>>, value::Error> {
+        Ok(lookup(self, path).map(Cow::Borrowed))
+    }
+}
+
+/// looks up a var of a template, a value that fails to serialize is a render error
+pub(crate) fn lookup_var<'c, C: Context + ?Sized>(
+    ctx: &'c C,
+    name: &str,
+    span: Span,
+) -> Result<Option<Cow<'c, Value>>, TemplusError> {
+    ctx.lookup(name).map_err(|err| {
+        TemplusError::RenderError((format!("cannot serialize var '{}': {}", name, err), span))
+    })
+}
+
+/// Wraps any `Serialize` type as a context. Only the value a var points at gets
+/// converted, the rest of the type is walked but never built.
+pub struct Serialized<'c, T: ?Sized>(pub &'c T);
+
+impl<T: Serialize + ?Sized> Context for Serialized<'_, T> {
+    fn lookup(&self, path: &str) -> Result<Option<Cow<'_, Value>>, value::Error> {
+        if path.is_empty() {
+            return value::to_value(self.0).map(|value| Some(Cow::Owned(value)));
+        }
+        let path = path.split('.').collect::<Vec<_>>();
+        match self.0.serialize(Seek { path: &path }) {
+            Ok(found) => Ok(found.map(Cow::Owned)),
+            Err(err) => Err(ser::Error::custom(err)),
+        }
+    }
+}
+
+/// Looks in the render context first and falls back to the globals of the environment.
+pub(crate) struct WithGlobals<'c, C: ?Sized> {
+    pub ctx: &'c C,
+    pub globals: &'c Value,
+}
+
+impl<C: Context + ?Sized> Context for WithGlobals<'_, C> {
+    fn lookup(&self, path: &str) -> Result<Option<Cow<'_, Value>>, value::Error> {
+        match self.ctx.lookup(path)? {
+            Some(value) => Ok(Some(value)),
+            None => self.globals.lookup(path),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SeekError(String);
+
+impl fmt::Display for SeekError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SeekError {}
+
+impl ser::Error for SeekError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SeekError(msg.to_string())
+    }
+}
+
+/// continues the search below `value`, or converts it once the path is used up
+fn descend<T: Serialize + ?Sized>(value: &T, path: &[&str]) -> Result<Option<Value>, SeekError> {
+    match path {
+        [] => value::to_value(value).map(Some).map_err(ser::Error::custom),
+        path => value.serialize(Seek { path }),
+    }
+}
+
+/// A serializer that only follows `path`, never empty. Scalars have no fields,
+/// so anything reaching one with path left over is not found.
+struct Seek<'p> {
+    path: &'p [&'p str],
+}
+
+impl<'p> Seek<'p> {
+    fn key(&self) -> &'p str {
+        self.path[0]
+    }
+
+    fn rest(&self) -> &'p [&'p str] {
+        &self.path[1..]
+    }
+}
+
+macro_rules! not_found {
+    ($($method:ident($($ty:ty),*)),* $(,)?) => {
+        $(
+            fn $method(self, $(_: $ty),*) -> Result<Self::Ok, Self::Error> {
+                Ok(None)
+            }
+        )*
+    };
+}
+
+impl<'p> ser::Serializer for Seek<'p> {
+    type Ok = Option<Value>;
+    type Error = SeekError;
+    type SerializeSeq = SeekSeq<'p>;
+    type SerializeTuple = SeekSeq<'p>;
+    type SerializeTupleStruct = SeekSeq<'p>;
+    type SerializeTupleVariant =
+        Whole<'p, <ValueSerializer as ser::Serializer>::SerializeTupleVariant>;
+    type SerializeMap = SeekMap<'p>;
+    type SerializeStruct = SeekStruct<'p>;
+    type SerializeStructVariant =
+        Whole<'p, <ValueSerializer as ser::Serializer>::SerializeStructVariant>;
+
+    not_found!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    );
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    // enums look like `{ "Variant": value }`, the same as in json
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        match self.key() == variant {
+            true => descend(value, self.rest()),
+            false => Ok(None),
+        }
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SeekSeq {
+            target: self.key().parse().ok(),
+            rest: self.rest(),
+            index: 0,
+            found: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    // variants with fields are rare in view models, those are simply built in full
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        let inner = ValueSerializer
+            .serialize_tuple_variant(name, index, variant, len)
+            .map_err(ser::Error::custom)?;
+        Ok(Whole {
+            inner,
+            path: self.path,
+        })
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(SeekMap {
+            key: self.key(),
+            rest: self.rest(),
+            matched: false,
+            found: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(SeekStruct {
+            key: self.key(),
+            rest: self.rest(),
+            found: None,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        let inner = ValueSerializer
+            .serialize_struct_variant(name, index, variant, len)
+            .map_err(ser::Error::custom)?;
+        Ok(Whole {
+            inner,
+            path: self.path,
+        })
+    }
+}
+
+struct SeekSeq<'p> {
+    target: Option<usize>,
+    rest: &'p [&'p str],
+    index: usize,
+    found: Option<Value>,
+}
+
+impl SeekSeq<'_> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SeekError> {
+        if self.target == Some(self.index) {
+            self.found = descend(value, self.rest)?;
+        }
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SeekSeq<'_> {
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SeekError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        Ok(self.found)
+    }
+}
+
+impl ser::SerializeTuple for SeekSeq<'_> {
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SeekError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        Ok(self.found)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeekSeq<'_> {
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SeekError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        Ok(self.found)
+    }
+}
+
+struct SeekMap<'p> {
+    key: &'p str,
+    rest: &'p [&'p str],
+    matched: bool,
+    found: Option<Value>,
+}
+
+impl ser::SerializeMap for SeekMap<'_> {
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SeekError> {
+        self.matched = self.found.is_none() && key.serialize(KeyEq(self.key))?;
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SeekError> {
+        if self.matched {
+            self.found = descend(value, self.rest)?;
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        Ok(self.found)
+    }
+}
+
+struct SeekStruct<'p> {
+    key: &'p str,
+    rest: &'p [&'p str],
+    found: Option<Value>,
+}
+
+impl ser::SerializeStruct for SeekStruct<'_> {
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SeekError> {
+        if key == self.key {
+            self.found = descend(value, self.rest)?;
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        Ok(self.found)
+    }
+}
+
+/// builds the full value, then looks up the path in it
+struct Whole<'p, S> {
+    inner: S,
+    path: &'p [&'p str],
+}
+
+impl<S> ser::SerializeTupleVariant for Whole<'_, S>
+where
+    S: ser::SerializeTupleVariant<Ok = Value, Error = value::Error>,
+{
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SeekError> {
+        self.inner
+            .serialize_field(value)
+            .map_err(ser::Error::custom)
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        let value = self.inner.end().map_err(ser::Error::custom)?;
+        Ok(lookup(&value, &self.path.join(".")).cloned())
+    }
+}
+
+impl<S> ser::SerializeStructVariant for Whole<'_, S>
+where
+    S: ser::SerializeStructVariant<Ok = Value, Error = value::Error>,
+{
+    type Ok = Option<Value>;
+    type Error = SeekError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SeekError> {
+        self.inner
+            .serialize_field(key, value)
+            .map_err(ser::Error::custom)
+    }
+
+    fn end(self) -> Result<Self::Ok, SeekError> {
+        let value = self.inner.end().map_err(ser::Error::custom)?;
+        Ok(lookup(&value, &self.path.join(".")).cloned())
+    }
+}
+
+/// compares a map key with a path segment, keys are matched by their json string form
+struct KeyEq<'p>(&'p str);
+
+impl KeyEq<'_> {
+    fn display<T: fmt::Display>(self, value: T) -> Result<bool, SeekError> {
+        Ok(value.to_string() == self.0)
+    }
+}
+
+impl ser::Serializer for KeyEq<'_> {
+    type Ok = bool;
+    type Error = SeekError;
+    type SerializeSeq = Impossible<bool, SeekError>;
+    type SerializeTuple = Impossible<bool, SeekError>;
+    type SerializeTupleStruct = Impossible<bool, SeekError>;
+    type SerializeTupleVariant = Impossible<bool, SeekError>;
+    type SerializeMap = Impossible<bool, SeekError>;
+    type SerializeStruct = Impossible<bool, SeekError>;
+    type SerializeStructVariant = Impossible<bool, SeekError>;
+
+    fn serialize_bool(self, v: bool) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_i8(self, v: i8) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_i16(self, v: i16) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_i32(self, v: i32) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_i64(self, v: i64) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_i128(self, v: i128) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_u8(self, v: u8) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_u16(self, v: u16) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_u32(self, v: u32) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_u64(self, v: u64) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_u128(self, v: u128) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_f32(self, v: f32) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_f64(self, v: f64) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_char(self, v: char) -> Result<bool, SeekError> {
+        self.display(v)
+    }
+    fn serialize_str(self, v: &str) -> Result<bool, SeekError> {
+        Ok(v == self.0)
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<bool, SeekError> {
+        Ok(variant == self.0)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<bool, SeekError> {
+        value.serialize(self)
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<bool, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_none(self) -> Result<bool, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<bool, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_unit(self) -> Result<bool, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<bool, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<bool, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, SeekError> {
+        Err(key_must_be_a_string())
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, SeekError> {
+        Err(key_must_be_a_string())
+    }
+}
+
+fn key_must_be_a_string() -> SeekError {
+    SeekError("key must be a string".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::Serialize;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct User {
+        name: String,
+        tags: Vec<&'static str>,
+        nick: Option<String>,
+        role: Role,
+    }
+
+    #[derive(Serialize)]
+    enum Role {
+        Admin,
+        Guest(u32),
+        Member { since: u32 },
+    }
+
+    fn get<T: Serialize>(value: &T, path: &str) -> Option<Value> {
+        Serialized(value).lookup(path).unwrap().map(Cow::into_owned)
+    }
+
+    fn user(role: Role) -> User {
+        User {
+            name: "lommix".to_owned(),
+            tags: vec!["a", "b"],
+            nick: None,
+            role,
+        }
+    }
+
+    #[test]
+    fn seek_matches_json_lookup() {
+        for role in [Role::Admin, Role::Guest(3), Role::Member { since: 2020 }] {
+            let user = user(role);
+            let json = Value::from(serde_json::to_value(&user).unwrap());
+            for path in [
+                "",
+                "name",
+                "tags",
+                "tags.1",
+                "tags.2",
+                "tags.x",
+                "nick",
+                "role",
+                "role.Guest",
+                "role.Member.since",
+                "name.len",
+                "missing",
+            ] {
+                assert_eq!(get(&user, path), lookup(&json, path).cloned(), "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn seek_maps() {
+        let mut map = HashMap::new();
+        map.insert("a", json!({ "b": [1, 2] }));
+        assert_eq!(get(&map, "a.b.1"), Some(Value::from(2)));
+        assert_eq!(get(&map, "b"), None);
+
+        let mut numbered = BTreeMap::new();
+        numbered.insert(7, "seven");
+        assert_eq!(get(&numbered, "7"), Some(Value::from("seven")));
+        assert_eq!(get(&Some(numbered), "7"), Some(Value::from("seven")));
+    }
+
+    #[test]
+    fn seek_reports_serialize_errors() {
+        let mut points = HashMap::new();
+        points.insert((1, 2), "a");
+        let ctx = json!({ "ok": 1 });
+        let mut map = HashMap::new();
+        map.insert("points", points);
+
+        assert!(Serialized(&map).lookup("points").is_err());
+        assert!(Serialized(&map).lookup("").is_err());
+        assert!(Serialized(&map).lookup("missing").unwrap().is_none());
+        assert!(Serialized(&ctx).lookup("ok").unwrap().is_some());
+    }
+}
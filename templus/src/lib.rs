@@ -1,16 +1,163 @@
+mod cache;
 #[allow(unused_variables)]
 pub mod compiler;
+pub mod context;
 pub mod renderer;
+pub mod shared;
+pub mod typed;
+pub mod value;
 
+/// Builds a context object. Keys are idents or string literals, values anything
+/// `Serialize`, `{ .. }` nests an object and `..base` copies in the fields of a
+/// struct or map, later entries win.
+///
+/// Every entry is one step of macro recursion, so contexts with more than about
+/// 120 entries need a higher limit like `#![recursion_limit = "256"]` in the crate using it.
+///
+/// ```
+/// #[derive(serde::Serialize)]
+/// struct User {
+///     name: &'static str,
+/// }
+///
+/// let ctx = templus::context! {
+///     ..User { name: "lommix" },
+///     admin => true,
+///     "data-id" => 7,
+///     site => { name => "templus" },
+/// }
+/// .unwrap();
+/// ```
 #[macro_export]
 macro_rules! context {
-    ($($key:ident => $value:expr),*) => {
-        {
-            let mut map = serde_json::Map::new();
-            $(
-                map.insert(stringify!($key).to_string(), serde_json::to_value($value).unwrap());
-            )*
-            serde_json::Value::Object(map)
-        }
+    () => {
+        ::std::result::Result::<_, $crate::value::Error>::Ok($crate::value::Value::Object(
+            ::std::collections::BTreeMap::new(),
+        ))
+    };
+    ($($entries:tt)*) => {{
+        (|| -> ::std::result::Result<$crate::value::Value, $crate::value::Error> {
+            let mut map = ::std::collections::BTreeMap::new();
+            $crate::__context_entries!(map; $($entries)*);
+            ::std::result::Result::Ok($crate::value::Value::Object(map))
+        })()
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __context_entries {
+    ($map:ident;) => {};
+    ($map:ident; .. $base:expr $(, $($rest:tt)*)?) => {
+        $crate::value::__context_spread(&mut $map, &$base)?;
+        $crate::__context_entries!($map; $($($rest)*)?);
+    };
+    // a value starting with `{` is a nested object and would be a hard error as an `expr`
+    ($map:ident; $key:tt => { $($inner:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::value::__context_nested(&mut $map, $crate::__context_key!($key), $crate::context! { $($inner)* })?;
+        $crate::__context_entries!($map; $($($rest)*)?);
+    };
+    ($map:ident; $key:tt => $value:expr $(, $($rest:tt)*)?) => {
+        $crate::value::__context_insert(&mut $map, $crate::__context_key!($key), &$value)?;
+        $crate::__context_entries!($map; $($($rest)*)?);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __context_key {
+    ($key:ident) => {
+        stringify!($key)
+    };
+    ($key:literal) => {
+        $key
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Serialize;
+    use serde_json::json;
+
+    use crate::value::Value;
+
+    #[derive(Serialize)]
+    struct User {
+        name: &'static str,
+        admin: bool,
+    }
+
+    #[test]
+    fn context_macro() {
+        let base = User {
+            name: "lommix",
+            admin: false,
+        };
+        let ctx = context! {
+            ..base,
+            admin => true,
+            "data-id" => 7,
+            site => {
+                name => "templus",
+                "nav" => { home => "/" },
+            },
+            tags => vec!["a", "b"],
+        }
+        .unwrap();
+        let expected = json!({
+            "name": "lommix",
+            "admin": true,
+            "data-id": 7,
+            "site": { "name": "templus", "nav": { "home": "/" } },
+            "tags": ["a", "b"],
+        });
+        assert_eq!(ctx, Value::from(expected));
+        assert_eq!(context! {}.unwrap(), Value::from(json!({})));
+        assert_eq!(context! { a => 1 }.unwrap(), Value::from(json!({ "a": 1 })));
+    }
+
+    #[test]
+    fn context_macro_many_keys() {
+        // five copies of 20 entries, close to what the default recursion limit allows
+        macro_rules! five_times {
+            (($($entries:tt)*) $($tail:tt)*) => {
+                context! {
+                    $($entries)* $($entries)* $($entries)* $($entries)* $($entries)*
+                    $($tail)*
+                }
+            };
+        }
+        let base = json!({ "name": "lommix" });
+        let ctx = five_times!(
+            (
+                a => 1, b => 2, c => 3, d => 4, e => 5, f => 6, g => 7, h => 8, i => 9, j => 10,
+                "k" => 1 + 1, l => "l", m => true, n => vec![1], o => None::<u8>,
+                p => 1, q => 2, r => 3, s => 4, t => 5,
+            )
+            nested => { ..base, deep => { x => 1 } },
+        )
+        .unwrap();
+        match &ctx {
+            Value::Object(map) => assert_eq!(map.len(), 21),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            crate::renderer::lookup(&ctx, "nested.deep.x"),
+            Some(&Value::from(1))
+        );
+        assert_eq!(
+            crate::renderer::lookup(&ctx, "nested.name"),
+            Some(&Value::from("lommix"))
+        );
+    }
+
+    #[test]
+    fn context_macro_errors() {
+        assert!(context! { ..5 }.is_err());
+        let mut bad_keys = HashMap::new();
+        bad_keys.insert(vec![1], 1);
+        assert!(context! { a => 1, nested => { map => bad_keys } }.is_err());
+    }
+}
@@ -1,201 +1,869 @@
 #![allow(unused)]
 
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
 
 use serde::{Serialize, Serializer};
 
+use crate::cache::RenderCache;
 use crate::compiler::{
+    bundle,
     error::TemplusError,
+    lexer::{Span, Syntax},
     parser::{Expression, Parser, Statement},
+    program::{self, Instr, Items, Loop, Loops},
 };
+use crate::context::{lookup_var, Context, Serialized, WithGlobals};
+use crate::value::{self, SafeHtml, Value};
+
+/// how many templates deep extends and imports may go
+const MAX_LINKS: usize = 64;
+
+/// What happens when a template references a var missing in the context.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UndefinedBehavior {
+    /// error in output, conditions and ranges
+    #[default]
+    Strict,
+    /// render nothing, conditions are false and ranges are empty
+    Lenient,
+    /// like lenient, but output renders a visible `{{ .name }}` marker
+    Debug,
+}
 
 pub struct Environment<'a> {
     pub templates: std::collections::HashMap<String, Statement<'a>>,
+    undefined_behavior: UndefinedBehavior,
+    syntax: Syntax,
+    auto_escape: bool,
+    /// always an object
+    globals: Value,
+    cache: Option<Mutex<RenderCache>>,
+    /// templates compiled to instructions by `flatten`
+    programs: HashMap<String, Vec<Instr<'a>>>,
+}
+
+impl<'a> Default for Environment<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> Environment<'a> {
     pub fn new() -> Self {
         Self {
             templates: std::collections::HashMap::new(),
+            undefined_behavior: UndefinedBehavior::default(),
+            syntax: Syntax::default(),
+            auto_escape: true,
+            globals: Value::Object(Default::default()),
+            cache: None,
+            programs: HashMap::new(),
+        }
+    }
+
+    pub fn set_undefined_behavior(&mut self, behavior: UndefinedBehavior) {
+        self.undefined_behavior = behavior;
+        self.clear_render_cache();
+    }
+
+    /// caches rendered output by template name and context, for pages rendered with
+    /// the same context over and over. Least recently used output is dropped once
+    /// there are `max_entries` entries or `max_bytes` of output and cached contexts.
+    /// Changing the environment clears the cache, editing `templates` directly
+    /// needs an `invalidate`.
+    pub fn enable_render_cache(&mut self, max_entries: usize, max_bytes: usize) {
+        self.cache = Some(Mutex::new(RenderCache::new(max_entries, max_bytes)));
+    }
+
+    pub fn disable_render_cache(&mut self) {
+        self.cache = None;
+    }
+
+    /// drops cached output of one template
+    pub fn invalidate(&self, name: &str) {
+        if let Some(cache) = &self.cache {
+            cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .invalidate(name);
+        }
+    }
+
+    pub fn clear_render_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    /// vars are html escaped unless turned off here, `SafeHtml` values and
+    /// `| safe` output never are
+    pub fn set_auto_escape(&mut self, auto_escape: bool) {
+        self.auto_escape = auto_escape;
+        self.clear_render_cache();
+    }
+
+    /// a var visible in every template and import, the render context wins
+    /// when it has a var of the same name
+    pub fn add_global<T: Serialize + ?Sized>(
+        &mut self,
+        name: &str,
+        value: &T,
+    ) -> Result<(), TemplusError> {
+        let value = value::to_value(value).map_err(|err| {
+            TemplusError::DeafultError(format!("cannot serialize global '{}': {}", name, err))
+        })?;
+        if let Value::Object(globals) = &mut self.globals {
+            globals.insert(name.to_owned(), value);
         }
+        self.clear_render_cache();
+        Ok(())
+    }
+
+    /// delimiters used by all following `parse` calls
+    pub fn set_syntax(&mut self, syntax: Syntax) -> Result<(), TemplusError> {
+        syntax.validate()?;
+        self.syntax = syntax;
+        self.clear_render_cache();
+        Ok(())
     }
 
     pub fn parse(&mut self, template: &'a str) -> Result<(), TemplusError> {
-        let mut parser = Parser::new(template.as_bytes());
+        let mut parser = Parser::with_syntax(template.as_bytes(), &self.syntax)?;
         for template in parser.parse()? {
-            match template {
-                Statement::Define(name, _, _) => {
-                    self.templates.insert(name.to_string(), template);
+            check_top_level(&template)?;
+            if let Statement::Define(name, _, _, _) = template {
+                self.templates.insert(name.to_string(), template);
+            }
+        }
+        // redefined templates change the output of everything importing them
+        self.clear_render_cache();
+        self.programs.clear();
+        Ok(())
+    }
+
+    /// serializes all parsed templates into a bundle for `load_compiled`,
+    /// lets templates be parsed at build time and shipped with the binary
+    pub fn compile(&self) -> Result<Vec<u8>, TemplusError> {
+        let mut templates = self.templates.iter().collect::<Vec<_>>();
+        templates.sort_by_key(|(name, _)| name.as_str());
+        let templates = templates.into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+        bundle::to_bytes(&templates)
+    }
+
+    /// registers the templates of a bundle written by `compile`
+    pub fn load_compiled(&mut self, bytes: &'a [u8]) -> Result<(), TemplusError> {
+        for template in bundle::from_bytes(bytes)? {
+            if let Statement::Define(name, _, _, _) = template {
+                self.templates.insert(name.to_string(), template);
+            }
+        }
+        self.clear_render_cache();
+        self.programs.clear();
+        Ok(())
+    }
+
+    /// resolves `extends`, block overrides and imports of every template and compiles
+    /// the result to a flat list of instructions, so renders skip looking up base
+    /// templates and blocks and do not recurse. Run it once all templates are parsed,
+    /// parsing again drops the result. Templates linking to missing templates or to
+    /// themselves keep rendering through the tree walk and fail once the link is reached.
+    pub fn flatten(&mut self) {
+        let mut programs = HashMap::new();
+        for (name, template) in &self.templates {
+            let mut out = vec![];
+            if self
+                .flatten_stmt(template, &HashMap::new(), &mut vec![], &mut out)
+                .is_ok()
+            {
+                programs.insert(name.clone(), out);
+            }
+        }
+        self.programs = programs;
+        self.clear_render_cache();
+    }
+
+    fn flatten_body(
+        &self,
+        stmts: &[Statement<'a>],
+        blocks: &HashMap<&'a str, &[Statement<'a>]>,
+        chain: &mut Vec<&'a str>,
+        out: &mut Vec<Instr<'a>>,
+    ) -> Result<(), TemplusError> {
+        for stmt in stmts {
+            self.flatten_stmt(stmt, blocks, chain, out)?;
+        }
+        Ok(())
+    }
+
+    fn flatten_stmt(
+        &self,
+        stmt: &Statement<'a>,
+        blocks: &HashMap<&'a str, &[Statement<'a>]>,
+        chain: &mut Vec<&'a str>,
+        out: &mut Vec<Instr<'a>>,
+    ) -> Result<(), TemplusError> {
+        match stmt {
+            Statement::Expression(expr) => match expr {
+                Expression::Variable(var_name, span) => out.push(Instr::Print(var_name, *span)),
+                Expression::Literal(text, _) | Expression::Number(text, _) => {
+                    out.push(Instr::Emit(text))
                 }
-                _ => {
-                    return Err(TemplusError::DeafultError(
-                        "File contains blocks outside of templates".to_owned(),
-                    ))
+                Expression::Arithmetic(_, _, _, span)
+                | Expression::Negate(_, span)
+                | Expression::Safe(_, span) => out.push(Instr::Eval(expr.clone(), *span)),
+                Expression::If(ifexpr, stmts, else_stmts) => {
+                    let cond = out.len();
+                    out.push(Instr::JumpIfFalse(ifexpr.clone(), 0));
+                    self.flatten_body(stmts, blocks, chain, out)?;
+                    if else_stmts.is_empty() {
+                        program::patch(out, cond);
+                    } else {
+                        let skip_else = out.len();
+                        out.push(Instr::Jump(0));
+                        program::patch(out, cond);
+                        self.flatten_body(else_stmts, blocks, chain, out)?;
+                        program::patch(out, skip_else);
+                    }
+                }
+                Expression::Range(over, stmts, span) => {
+                    let start = out.len();
+                    match **over {
+                        Expression::Variable(var, var_span) => {
+                            out.push(Instr::IterVar(var, var_span, 0))
+                        }
+                        Expression::Number(lit, lit_span) => match lit.parse::<i64>() {
+                            Ok(num) => out.push(Instr::IterNum(num, 0)),
+                            Err(_) => {
+                                out.push(Instr::Fail(
+                                    "range literal required a valid number",
+                                    lit_span,
+                                ));
+                                return Ok(());
+                            }
+                        },
+                        _ => {
+                            out.push(Instr::Fail("Can only range over vars or numbers", *span));
+                            return Ok(());
+                        }
+                    }
+                    self.flatten_body(stmts, blocks, chain, out)?;
+                    out.push(Instr::Next(start + 1));
+                    program::patch(out, start);
+                }
+            },
+            Statement::Block(name, stmts, _) => match blocks.get(name) {
+                // a block of the same name inside the override keeps its own body
+                Some(block) => {
+                    let mut inner = blocks.clone();
+                    inner.remove(name);
+                    self.flatten_body(block, &inner, chain, out)?;
+                }
+                None => self.flatten_body(stmts, blocks, chain, out)?,
+            },
+            Statement::Define(name, extends, stmts, span) => {
+                chain.push(name);
+                match extends {
+                    Some(extends_name) => {
+                        let base_tmpl = self.linked(extends_name, chain, *span, || {
+                            format!("base template '{}' not found", extends_name)
+                        })?;
+                        // blocks of templates further down the chain win
+                        let mut over = stmts
+                            .iter()
+                            .filter_map(|s| match s {
+                                Statement::Block(name, stmts, _) => Some((*name, stmts.as_slice())),
+                                _ => None,
+                            })
+                            .collect::<HashMap<_, _>>();
+                        over.extend(blocks.iter().map(|(k, v)| (*k, *v)));
+                        self.flatten_stmt(base_tmpl, &over, chain, out)?;
+                    }
+                    None => self.flatten_body(stmts, blocks, chain, out)?,
                 }
+                chain.pop();
+            }
+            Statement::Import(tmpl_name, span) => {
+                let tmpl = self.linked(tmpl_name, chain, *span, || {
+                    format!("Cannot import non existing template: {}", tmpl_name)
+                })?;
+                self.flatten_stmt(tmpl, &HashMap::new(), chain, out)?;
             }
         }
         Ok(())
     }
 
-    pub fn render(&self, name: &str, ctx: &serde_json::Value) -> Result<String, TemplusError> {
+    /// the template `name` links to, unless it is already part of the chain
+    fn linked(
+        &self,
+        name: &str,
+        chain: &[&str],
+        span: Span,
+        missing: impl FnOnce() -> String,
+    ) -> Result<&Statement<'a>, TemplusError> {
+        if chain.contains(&name) {
+            return Err(TemplusError::RenderError((
+                format!("template '{}' includes itself", name),
+                span,
+            )));
+        }
+        if chain.len() >= MAX_LINKS {
+            return Err(TemplusError::RenderError((
+                format!(
+                    "template '{}' extends or imports more than {} levels deep",
+                    name, MAX_LINKS
+                ),
+                span,
+            )));
+        }
+        self.templates
+            .get(name)
+            .ok_or_else(|| TemplusError::RenderError((missing(), span)))
+    }
+
+    /// collects every error in a template instead of stopping at the first,
+    /// nothing gets registered
+    pub fn check(&self, template: &'a str) -> Vec<TemplusError> {
+        let mut parser = match Parser::with_syntax(template.as_bytes(), &self.syntax) {
+            Ok(parser) => parser,
+            Err(err) => return vec![err],
+        };
+        let (templates, mut errors) = parser.parse_recovering();
+        errors.extend(templates.iter().filter_map(|t| check_top_level(t).err()));
+        errors.sort_by_key(|err| err.span().map(|span| (span.line(), span.column())));
+        errors
+    }
+
+    /// checks that every template `name` extends or imports is registered
+    pub fn validate(&self, name: &str) -> Vec<TemplusError> {
+        let mut errors = vec![];
+        match self.templates.get(name) {
+            Some(template) => self.validate_stmt(template, &mut errors),
+            None => errors.push(TemplusError::DeafultError("template not found".to_owned())),
+        }
+        errors
+    }
+
+    fn validate_stmt(&self, stmt: &Statement<'a>, errors: &mut Vec<TemplusError>) {
+        let stmts = match stmt {
+            Statement::Define(_, extends, stmts, span) => {
+                if let Some(extends_name) = extends.filter(|n| !self.templates.contains_key(*n)) {
+                    errors.push(TemplusError::RenderError((
+                        format!("base template '{}' not found", extends_name),
+                        *span,
+                    )));
+                }
+                stmts
+            }
+            Statement::Block(_, stmts, _) => stmts,
+            Statement::Expression(Expression::Range(_, stmts, _)) => stmts,
+            Statement::Expression(Expression::If(_, btrue, bfalse)) => {
+                for s in btrue {
+                    self.validate_stmt(s, errors);
+                }
+                bfalse
+            }
+            Statement::Expression(_) => return,
+            Statement::Import(tmpl_name, span) => {
+                if !self.templates.contains_key(*tmpl_name) {
+                    errors.push(TemplusError::RenderError((
+                        format!("Cannot import non existing template: {}", tmpl_name),
+                        *span,
+                    )));
+                }
+                return;
+            }
+        };
+        for s in stmts {
+            self.validate_stmt(s, errors);
+        }
+    }
+
+    /// renders with any serializable context, vars are serialized only when a template
+    /// uses them instead of converting the whole context to json up front
+    pub fn render<T: Serialize + ?Sized>(
+        &self,
+        name: &str,
+        ctx: &T,
+    ) -> Result<String, TemplusError> {
+        let Some(cache) = &self.cache else {
+            return self.render_with(name, &Serialized(ctx));
+        };
+        let ctx = value::to_value(ctx).map_err(|err| {
+            TemplusError::DeafultError(format!("cannot serialize context: {}", err))
+        })?;
+        let cached = cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name, &ctx);
+        if let Some(out) = cached {
+            return Ok(out);
+        }
+        // no lock while rendering, two threads may render the same page at once
+        let out = self.render_with(name, &ctx)?;
+        cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, ctx, out.clone());
+        Ok(out)
+    }
+
+    fn render_with<C: Context + ?Sized>(
+        &self,
+        name: &str,
+        ctx: &C,
+    ) -> Result<String, TemplusError> {
+        let ctx = WithGlobals {
+            ctx,
+            globals: &self.globals,
+        };
+        if let Some(program) = self.programs.get(name) {
+            return self.run(program, &ctx);
+        }
+
         let template = self
             .templates
             .get(name)
             .ok_or(TemplusError::DeafultError("template not found".to_owned()))?;
+        self.render_stmt(template, &ctx, None, 0)
+    }
 
+    /// renders a flattened template, ranges keep their state on a stack instead of recursing
+    fn run<'c, C: Context + ?Sized>(
+        &'c self,
+        program: &'c [Instr<'a>],
+        ctx: &'c C,
+    ) -> Result<String, TemplusError> {
         let mut out = String::new();
-        Ok(self.render_stmt(template, ctx, None)?)
+        let mut loops = Loops::default();
+        let mut pc = 0;
+        while let Some(instr) = program.get(pc) {
+            pc += 1;
+            let jump = match instr {
+                Instr::Emit(text) => {
+                    out += text;
+                    continue;
+                }
+                Instr::Jump(target) => Some(*target),
+                Instr::IterNum(num, end) => match *num > 0 {
+                    true => {
+                        loops.push(Loop::Times(*num));
+                        None
+                    }
+                    false => Some(*end),
+                },
+                Instr::IterVar(var, span, end) => {
+                    match self.range_items(&loops, ctx, var, *span)? {
+                        Some(items)
+                            if loops.array(&items).is_some_and(|items| !items.is_empty()) =>
+                        {
+                            loops.push(Loop::Items(items, 0));
+                            None
+                        }
+                        Some(_) => Some(*end),
+                        None => {
+                            // outside of strict mode a missing var is an empty range
+                            self.render_undefined(var, *span)?;
+                            Some(*end)
+                        }
+                    }
+                }
+                Instr::Next(body) => loops.next().then_some(*body),
+                Instr::Fail(msg, span) => {
+                    return Err(TemplusError::RenderError((msg.to_string(), *span)))
+                }
+                // inside a range over items the current item is the context
+                _ => match loops.current() {
+                    Some((_, item)) => {
+                        let item = WithGlobals {
+                            ctx: item,
+                            globals: &self.globals,
+                        };
+                        self.step(instr, &item, &mut out)?
+                    }
+                    None => self.step(instr, ctx, &mut out)?,
+                },
+            };
+            if let Some(target) = jump {
+                pc = target;
+            }
+        }
+        Ok(out)
+    }
+
+    /// runs the instructions that depend on the context, returns where to jump to
+    fn step<C: Context + ?Sized>(
+        &self,
+        instr: &Instr<'a>,
+        ctx: &C,
+        out: &mut String,
+    ) -> Result<Option<usize>, TemplusError> {
+        match instr {
+            Instr::Print(var_name, span) => self.write_var(out, ctx, var_name, *span)?,
+            Instr::Eval(expr, span) => self.write_eval(out, ctx, expr, *span)?,
+            Instr::JumpIfFalse(ifexpr, target) if !ifexpr.eval(ctx, self.undefined_behavior)? => {
+                return Ok(Some(*target))
+            }
+            _ => {}
+        }
+        Ok(None)
     }
 
-    fn extract_blocks(stmt: &'a Vec<Statement<'a>>) -> HashMap<String, &'a Vec<Statement<'a>>> {
+    /// the array a range over `var` walks, `None` if the var is missing. Arrays are
+    /// borrowed where possible, the tree walk does not copy them either
+    fn range_items<'c, C: Context + ?Sized>(
+        &'c self,
+        loops: &Loops<'c>,
+        ctx: &'c C,
+        var: &'c str,
+        span: Span,
+    ) -> Result<Option<Items<'c>>, TemplusError> {
+        let not_array =
+            || TemplusError::RenderError((format!("cannot range over non array '{}'", var), span));
+        let value = match loops.current() {
+            None => lookup_var(ctx, var, span)?,
+            Some((range, item)) => match loops.borrowed_item(range) {
+                Some(item) => lookup(item, var).map(Cow::Borrowed),
+                // items owned by an outer range are looked up again instead of copied
+                None => match lookup(item, var) {
+                    Some(Value::Array(_)) => return Ok(Some(Items::Field(range, var))),
+                    Some(_) => return Err(not_array()),
+                    None => None,
+                },
+            }
+            .or_else(|| lookup(&self.globals, var).map(Cow::Borrowed)),
+        };
+        match value {
+            None => Ok(None),
+            Some(Cow::Borrowed(Value::Array(items))) => Ok(Some(Items::Borrowed(items))),
+            Some(Cow::Owned(Value::Array(items))) => Ok(Some(Items::Owned(items))),
+            Some(_) => Err(not_array()),
+        }
+    }
+
+    fn extract_blocks(stmt: &'a [Statement<'a>]) -> HashMap<String, &'a Vec<Statement<'a>>> {
         let mut block_map = HashMap::new();
         for st in stmt {
-            match st {
-                Statement::Block(name, content) => {
-                    block_map.insert(name.to_string(), content);
-                }
-                _ => (),
-            };
+            if let Statement::Block(name, content, _) = st {
+                block_map.insert(name.to_string(), content);
+            }
         }
         block_map
     }
 
     // mhhhhh recursive functions feel good
-    fn render_stmt(
+    fn render_stmt<C: Context + ?Sized>(
         &self,
         stmt: &Statement<'a>,
-        ctx: &serde_json::Value,
+        ctx: &C,
         overwrites: Option<&HashMap<String, &'a Vec<Statement<'a>>>>,
+        links: usize,
     ) -> Result<String, TemplusError> {
         let mut out = String::new();
         match stmt {
             Statement::Expression(expr) => {
-                out += (self.render_expr(expr, ctx, overwrites)?).as_str()
+                out += (self.render_expr(expr, ctx, overwrites, links)?).as_str()
             }
-            Statement::Define(name, extends, stmts) => match extends {
+            // also stops templates that include themselves
+            Statement::Define(name, _, _, span) if links >= MAX_LINKS => {
+                return Err(TemplusError::RenderError((
+                    format!(
+                        "template '{}' extends or imports more than {} levels deep",
+                        name, MAX_LINKS
+                    ),
+                    *span,
+                )))
+            }
+            Statement::Define(name, extends, stmts, span) => match extends {
                 Some(extends_name) => {
                     let base_tmpl =
                         self.templates
                             .get(*extends_name)
-                            .ok_or(TemplusError::DeafultError(format!(
-                                "base template '{}' not found",
-                                extends_name
+                            .ok_or(TemplusError::RenderError((
+                                format!("base template '{}' not found", extends_name),
+                                *span,
                             )))?;
-                    let over = Environment::extract_blocks(stmts);
-                    out += self.render_stmt(base_tmpl, ctx, Some(&over))?.as_str();
+                    // blocks of templates further down the chain win
+                    let mut over = Environment::extract_blocks(stmts);
+                    over.extend(
+                        overwrites
+                            .into_iter()
+                            .flatten()
+                            .map(|(k, v)| (k.clone(), *v)),
+                    );
+                    out += self
+                        .render_stmt(base_tmpl, ctx, Some(&over), links + 1)?
+                        .as_str();
                 }
 
                 None => {
                     for s in stmts {
-                        out += self.render_stmt(s, ctx, overwrites)?.as_str();
+                        out += self.render_stmt(s, ctx, overwrites, links + 1)?.as_str();
                     }
                 }
             },
-            Statement::Block(name, stmts) => match overwrites {
+            Statement::Block(name, stmts, _) => match overwrites {
                 Some(ow) => {
                     if let Some(block) = ow.get(*name) {
+                        // a block of the same name inside the override keeps its own body
+                        let mut inner = ow.clone();
+                        inner.remove(*name);
                         for s in *block {
-                            out += self.render_stmt(s, ctx, overwrites)?.as_str();
+                            out += self.render_stmt(s, ctx, Some(&inner), links)?.as_str();
                         }
                     } else {
                         for s in stmts {
-                            out += self.render_stmt(s, ctx, overwrites)?.as_str();
+                            out += self.render_stmt(s, ctx, overwrites, links)?.as_str();
                         }
                     }
                 }
                 None => {
                     for s in stmts {
-                        out += self.render_stmt(s, ctx, overwrites)?.as_str();
+                        out += self.render_stmt(s, ctx, overwrites, links)?.as_str();
                     }
                 }
             },
-            Statement::Import(tmpl_name) => {
+            Statement::Import(tmpl_name, span) => {
                 let tmpl = self
                     .templates
                     .get(*tmpl_name)
-                    .ok_or(TemplusError::DeafultError(format!(
-                        "Cannot import non existing template: {}",
-                        tmpl_name
+                    .ok_or(TemplusError::RenderError((
+                        format!("Cannot import non existing template: {}", tmpl_name),
+                        *span,
                     )))?;
-                out += self.render_stmt(tmpl, ctx, None)?.as_str();
+                out += self.render_stmt(tmpl, ctx, None, links)?.as_str();
             }
         }
 
         Ok(out)
     }
 
-    fn render_expr(
+    fn render_expr<C: Context + ?Sized>(
         &self,
         expr: &Expression<'a>,
-        ctx: &serde_json::Value,
+        ctx: &C,
         overwrites: Option<&HashMap<String, &'a Vec<Statement<'a>>>>,
+        links: usize,
     ) -> Result<String, TemplusError> {
         let mut out = String::new();
 
         match expr {
-            Expression::Variable(var_name) => {
-                out += ctx
-                    .get(var_name)
-                    .ok_or(TemplusError::DeafultError("var not found".to_owned()))?
-                    .as_str()
-                    .ok_or(TemplusError::DeafultError("var not stringable".to_owned()))?
-            }
-            Expression::Literal(literal) => out += literal,
+            Expression::Variable(var_name, span) => {
+                self.write_var(&mut out, ctx, var_name, *span)?
+            }
+            Expression::Literal(literal, _) => out += literal,
+            Expression::Number(num, _) => out += num,
+            Expression::Arithmetic(_, _, _, span)
+            | Expression::Negate(_, span)
+            | Expression::Safe(_, span) => self.write_eval(&mut out, ctx, expr, *span)?,
             Expression::If(ifexpr, stmts, else_stmts) => {
-                if ifexpr.eval(ctx)? {
+                if ifexpr.eval(ctx, self.undefined_behavior)? {
                     for s in stmts {
-                        out += self.render_stmt(s, ctx, None)?.as_str();
+                        out += self.render_stmt(s, ctx, overwrites, links)?.as_str();
                     }
                 } else {
                     for s in else_stmts {
-                        out += self.render_stmt(s, ctx, None)?.as_str();
+                        out += self.render_stmt(s, ctx, overwrites, links)?.as_str();
                     }
                 }
             }
-            Expression::Range(expr, stmts) => match **expr {
-                Expression::Variable(var) => {
-                    match ctx
-                        .get(var)
-                        .ok_or(TemplusError::DeafultError("var not found".to_owned()))?
-                    {
-                        serde_json::Value::Array(array) => {
+            Expression::Range(expr, stmts, span) => match **expr {
+                Expression::Variable(var, var_span) => {
+                    let value = match lookup_var(ctx, var, var_span)? {
+                        Some(value) => value,
+                        None => {
+                            // outside of strict mode a missing var is an empty range
+                            self.render_undefined(var, var_span)?;
+                            return Ok(out);
+                        }
+                    };
+                    match value.as_ref() {
+                        Value::Array(array) => {
                             for item in array {
+                                let item = WithGlobals {
+                                    ctx: item,
+                                    globals: &self.globals,
+                                };
                                 for stmt in stmts {
-                                    out += self.render_stmt(stmt, item, overwrites)?.as_str();
+                                    out +=
+                                        self.render_stmt(stmt, &item, overwrites, links)?.as_str();
                                 }
                             }
                         }
                         _ => {
-                            return Err(TemplusError::DeafultError(
-                                "cannot range over non array".to_owned(),
-                            ))
+                            return Err(TemplusError::RenderError((
+                                format!("cannot range over non array '{}'", var),
+                                var_span,
+                            )))
                         }
                     }
                 }
-                Expression::Literal(lit) => {
+                Expression::Number(lit, lit_span) => {
                     let num = lit.parse::<i64>().map_err(|_| {
-                        TemplusError::DeafultError(
+                        TemplusError::RenderError((
                             "range literal required a valid number".to_owned(),
-                        )
+                            lit_span,
+                        ))
                     })?;
 
                     for i in 0..num {
                         for stmt in stmts {
                             // fix key pass trough at some point
-                            out += self.render_stmt(stmt, ctx, overwrites)?.as_str();
+                            out += self.render_stmt(stmt, ctx, overwrites, links)?.as_str();
                         }
                     }
                 }
                 _ => {
-                    return Err(TemplusError::DeafultError(
+                    return Err(TemplusError::RenderError((
                         "Can only range over vars or numbers".to_owned(),
-                    ))
+                        *span,
+                    )))
                 }
             },
         }
 
         Ok(out)
     }
+
+    fn write_var<C: Context + ?Sized>(
+        &self,
+        out: &mut String,
+        ctx: &C,
+        var_name: &str,
+        span: Span,
+    ) -> Result<(), TemplusError> {
+        match lookup_var(ctx, var_name, span)? {
+            Some(value) => {
+                let text = display_value(&value).ok_or(TemplusError::RenderError((
+                    format!("var '{}' not stringable", var_name),
+                    span,
+                )))?;
+                self.write_escaped(out, &value, &text);
+            }
+            None => *out += self.render_undefined(var_name, span)?.as_str(),
+        }
+        Ok(())
+    }
+
+    fn write_eval<C: Context + ?Sized>(
+        &self,
+        out: &mut String,
+        ctx: &C,
+        expr: &Expression<'a>,
+        span: Span,
+    ) -> Result<(), TemplusError> {
+        // a missing var leaves the whole result empty, debug points at the first one instead
+        if self.undefined_behavior == UndefinedBehavior::Debug {
+            if let Some((name, span)) = missing_var(expr, ctx) {
+                *out += self.render_undefined(name, span)?.as_str();
+                return Ok(());
+            }
+        }
+        let value = expr.eval(ctx, self.undefined_behavior)?;
+        let text = display_value(&value).ok_or(TemplusError::RenderError((
+            "result not stringable".to_owned(),
+            span,
+        )))?;
+        self.write_escaped(out, &value, &text);
+        Ok(())
+    }
+
+    fn write_escaped(&self, out: &mut String, value: &Value, text: &str) {
+        match value {
+            Value::SafeHtml(_) => *out += text,
+            _ if self.auto_escape => escape_html(out, text),
+            _ => *out += text,
+        }
+    }
+
+    fn render_undefined(&self, name: &str, span: Span) -> Result<String, TemplusError> {
+        match self.undefined_behavior {
+            UndefinedBehavior::Strict => Err(TemplusError::RenderError((
+                format!("var '{}' not found", name),
+                span,
+            ))),
+            UndefinedBehavior::Lenient => Ok(String::new()),
+            UndefinedBehavior::Debug => Ok(format!(
+                "{} .{} {}",
+                self.syntax.block_start, name, self.syntax.block_end
+            )),
+        }
+    }
+}
+
+/// the first var in `expr` the context does not have
+fn missing_var<'e, C: Context + ?Sized>(
+    expr: &'e Expression<'_>,
+    ctx: &C,
+) -> Option<(&'e str, Span)> {
+    match expr {
+        // a var that fails to serialize is there, eval reports it
+        Expression::Variable(name, span) => {
+            matches!(ctx.lookup(name), Ok(None)).then_some((*name, *span))
+        }
+        Expression::Arithmetic(left, _, right, _) => {
+            missing_var(left, ctx).or_else(|| missing_var(right, ctx))
+        }
+        Expression::Negate(inner, _) | Expression::Safe(inner, _) => missing_var(inner, ctx),
+        _ => None,
+    }
+}
+
+/// only defines and plain html may sit at the top of a file
+fn check_top_level(template: &Statement<'_>) -> Result<(), TemplusError> {
+    match template {
+        Statement::Define(..) => Ok(()),
+        // html outside of defines is ignored
+        Statement::Expression(Expression::Literal(_, _)) => Ok(()),
+        _ => Err(TemplusError::SyntaxError((
+            "File contains blocks outside of templates".to_owned(),
+            template.span(),
+        ))),
+    }
+}
+
+/// how a value looks in the output, arrays and objects have no string form
+pub(crate) fn display_value(value: &Value) -> Option<Cow<'_, str>> {
+    match value {
+        Value::String(string) | Value::SafeHtml(string) => Some(Cow::Borrowed(string)),
+        // ints stay exact and floats print in their shortest form
+        Value::Number(num) => Some(Cow::Owned(num.to_string())),
+        Value::Bool(bool) => Some(Cow::Owned(bool.to_string())),
+        Value::Null => Some(Cow::Borrowed("")),
+        _ => None,
+    }
+}
+
+/// writes `text` with `& < > " '` replaced by their entities
+pub fn escape_html(out: &mut String, text: &str) {
+    let mut rest = text;
+    while let Some(i) = rest.find(['&', '<', '>', '"', '\'']) {
+        *out += &rest[..i];
+        *out += match rest.as_bytes()[i] {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'"' => "&quot;",
+            _ => "&#39;",
+        };
+        rest = &rest[i + 1..];
+    }
+    *out += rest;
+}
+
+/// resolves a dotted var path like `user.name`, an empty path is the context itself
+pub(crate) fn lookup<'c>(ctx: &'c Value, path: &str) -> Option<&'c Value> {
+    if path.is_empty() {
+        return Some(ctx);
+    }
+    path.split('.').try_fold(ctx, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(array) => key.parse::<usize>().ok().and_then(|i| array.get(i)),
+        _ => None,
+    })
 }
 
 #[derive(Serialize)]
@@ -222,7 +890,7 @@ mod test {
 
         let out = env.render("foo", &serde_json::to_value(ctx).unwrap());
         println!("---------------------------- OUTPUT:");
-        print!("{}\n", out.unwrap());
+        println!("{}", out.unwrap());
         println!("----------------------------");
     }
 
@@ -240,7 +908,586 @@ mod test {
 
         let out = env.render("test", &serde_json::to_value(ctx).unwrap());
         println!("---------------------------- OUTPUT:");
-        print!("{}\n", out.unwrap());
+        println!("{}", out.unwrap());
         println!("----------------------------");
     }
+
+    #[test]
+    fn test_render_error_span() {
+        let tmpl = "{{ define 'test' }}\n\t<p>{{ .missing }}</p>\n{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        match env.render("test", &serde_json::json!({})) {
+            Err(TemplusError::RenderError((_, span))) => {
+                assert_eq!((span.line(), span.column()), (2, 8));
+            }
+            other => panic!("expected render error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_undefined_behavior() {
+        let tmpl = "{{ define 'test' }}<p>{{ .missing }}</p>{{ if .missing }}yes{{ end }}{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+        let ctx = serde_json::json!({});
+
+        assert!(env.render("test", &ctx).is_err());
+
+        env.set_undefined_behavior(UndefinedBehavior::Lenient);
+        assert_eq!(env.render("test", &ctx).unwrap(), "<p></p>");
+
+        env.set_undefined_behavior(UndefinedBehavior::Debug);
+        assert_eq!(env.render("test", &ctx).unwrap(), "<p>{{ .missing }}</p>");
+    }
+
+    #[test]
+    fn test_undefined_in_conditions_and_eval() {
+        let tmpl = "{{ define 'if' }}{{ if .missing }}yes{{ else }}no{{ end }}{{ end }}\
+            {{ define 'eval' }}[{{ .missing | safe }}][{{ .n + .missing * 2 }}][{{ -.missing }}]{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+        let ctx = serde_json::json!({ "n": 1 });
+
+        for flatten in [false, true] {
+            if flatten {
+                env.flatten();
+            }
+            // strict is the default, before it existed a missing var in a condition was false
+            env.set_undefined_behavior(UndefinedBehavior::Strict);
+            assert!(env.render("if", &ctx).is_err());
+            assert!(env.render("eval", &ctx).is_err());
+
+            env.set_undefined_behavior(UndefinedBehavior::Lenient);
+            assert_eq!(env.render("if", &ctx).unwrap(), "no");
+            assert_eq!(env.render("eval", &ctx).unwrap(), "[][][]");
+
+            env.set_undefined_behavior(UndefinedBehavior::Debug);
+            assert_eq!(env.render("if", &ctx).unwrap(), "no");
+            assert_eq!(
+                env.render("eval", &ctx).unwrap(),
+                "[{{ .missing }}][{{ .missing }}][{{ .missing }}]"
+            );
+        }
+    }
+
+    #[test]
+    fn test_serialize_errors_are_render_errors() {
+        let tmpl = "{{ define 'out' }}{{ .points }}{{ end }}\
+            {{ define 'if' }}{{ if .points }}x{{ end }}{{ end }}\
+            {{ define 'range' }}{{ range .points }}x{{ end }}{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+        env.set_undefined_behavior(UndefinedBehavior::Lenient);
+        let mut ctx = HashMap::new();
+        ctx.insert("points", HashMap::from([((1, 2), "a")]));
+
+        for name in ["out", "if", "range"] {
+            match env.render(name, &ctx) {
+                Err(TemplusError::RenderError((msg, _))) => {
+                    assert!(msg.contains("cannot serialize var 'points'"), "{}", msg)
+                }
+                other => panic!("{}: {:?}", name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_nested_var() {
+        let tmpl = "{{ define 'test' }}<p>{{ .user.name }}</p>{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({ "user": { "name": "lommix" } });
+        assert_eq!(env.render("test", &ctx).unwrap(), "<p>lommix</p>");
+    }
+
+    #[test]
+    fn test_render_numbers() {
+        let tmpl = "{{ define 'test' }}\
+            {{ .price }},{{ .big }}\
+            {{ if .price > 3 }},gt{{ end }}\
+            {{ if .price <= -1.5 }},lte{{ end }}\
+            {{ if .big > 9223372036854775807 }},big{{ end }}\
+            {{ if .count == 2.0 }},eq{{ end }}\
+            {{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({ "price": 3.5, "big": u64::MAX, "count": 2 });
+        assert_eq!(
+            env.render("test", &ctx).unwrap(),
+            "3.5,18446744073709551615,gt,big,eq"
+        );
+    }
+
+    #[test]
+    fn test_render_arithmetic() {
+        let tmpl = "{{ define 'test' }}\
+            {{ .page + 1 }},{{ 2 + .page * 3 }},{{ (2 + .page) * 3 }},{{ -.page - 1 }},\
+            {{ .price / 2 }},{{ 7 / 2 }},{{ 'page ' + .page }}\
+            {{ if .count % 2 == 0 }},even{{ end }}\
+            {{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({ "page": 4, "price": 5.0, "count": 10 });
+        assert_eq!(
+            env.render("test", &ctx).unwrap(),
+            "5,14,18,-5,2.5,3,page 4,even"
+        );
+    }
+
+    #[test]
+    fn test_render_arithmetic_errors() {
+        let mut env = Environment::new();
+        env.parse("{{ define 'div' }}{{ .page / 0 }}{{ end }}{{ define 'type' }}{{ .page - .name }}{{ end }}")
+            .unwrap();
+        let ctx = serde_json::json!({ "page": 4, "name": "lommix" });
+
+        assert!(matches!(
+            env.render("div", &ctx),
+            Err(TemplusError::RenderError(_))
+        ));
+        assert!(matches!(
+            env.render("type", &ctx),
+            Err(TemplusError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_render_string_compare_and_in() {
+        let tmpl = "{{ define 'test' }}\
+            {{ if .name < 'mario' }}lt{{ end }}\
+            {{ if .name >= 'lommix' }},gte{{ end }}\
+            {{ if 'admin' in .roles }},role{{ end }}\
+            {{ if 2 in .ids }},id{{ end }}\
+            {{ if 'theme' in .settings }},key{{ end }}\
+            {{ if 'mm' in .name }},sub{{ end }}\
+            {{ if .roles contains 'guest' }},guest{{ end }}\
+            {{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({
+            "name": "lommix",
+            "roles": ["user", "admin"],
+            "ids": [1.0, 2.0],
+            "settings": { "theme": "dark" },
+        });
+        assert_eq!(env.render("test", &ctx).unwrap(), "lt,gte,role,id,key,sub");
+    }
+
+    #[test]
+    fn test_render_in_type_error() {
+        let mut env = Environment::new();
+        env.parse("{{ define 'test' }}{{ if 1 in .count }}x{{ end }}{{ end }}")
+            .unwrap();
+
+        assert!(matches!(
+            env.render("test", &serde_json::json!({ "count": 3 })),
+            Err(TemplusError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_render_whitespace() {
+        let tmpl = "{{ define 'test' }}Hello {{.name}}!\n<pre>\n  a\n    b\n</pre>{{ end }}\n\
+            {{ define 'trim' }}\n<ul>\n  {{- range 2 }}\n  <li>{{ .name -}}  </li>\n  {{- end }}\n</ul>{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({ "name": "lommix" });
+        assert_eq!(
+            env.render("test", &ctx).unwrap(),
+            "Hello lommix!\n<pre>\n  a\n    b\n</pre>"
+        );
+        assert_eq!(
+            env.render("trim", &ctx).unwrap(),
+            "\n<ul>\n  <li>lommix</li>\n  <li>lommix</li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn test_render_comments() {
+        let tmpl = "{{/* file header */}}\
+            {{ define 'test' }}<p>{{/* not\n for {{ .clients }} */}}{{ .name }}</p>{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({ "name": "lommix" });
+        assert_eq!(env.render("test", &ctx).unwrap(), "<p>lommix</p>");
+    }
+
+    #[test]
+    fn test_render_raw() {
+        let tmpl = "{{ define 'test' }}\
+            <div id=\"app\">{{ raw -}}\n  {{ message | upper }}\n{{- end }}</div>\
+            <p>{{ '{{' }} .name }}</p>\
+            {{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        assert_eq!(
+            env.render("test", &serde_json::json!({})).unwrap(),
+            "<div id=\"app\">{{ message | upper }}</div><p>{{ .name }}</p>"
+        );
+    }
+
+    #[test]
+    fn test_render_custom_syntax() {
+        let tmpl = "<% define 'test' %><div>{{ vue }}</div>\
+            <%# a comment #%><p><%- .name -%> </p>\
+            <% if .name == 'lommix' %>[[ x ]]<% end %><% end %>";
+        let mut env = Environment::new();
+        env.set_syntax(Syntax::new("<%", "%>").with_comments("#", "#"))
+            .unwrap();
+        env.parse(tmpl).unwrap();
+
+        let ctx = serde_json::json!({ "name": "lommix" });
+        assert_eq!(
+            env.render("test", &ctx).unwrap(),
+            "<div>{{ vue }}</div><p>lommix</p>[[ x ]]"
+        );
+        assert!(env.set_syntax(Syntax::new("", "]]")).is_err());
+    }
+
+    #[test]
+    fn test_check_reports_all_errors() {
+        let tmpl = "{{ .stray }}\n\
+                    {{ define 'a' }}{{ if }}{{ end }}{{ end }}\n\
+                    {{ define 'b' }}{{ .a ~ }}{{ end }}";
+        let mut env = Environment::new();
+        let errors = env.check(tmpl);
+        let lines = errors
+            .iter()
+            .map(|err| err.span().unwrap().line())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 2, 3]);
+        assert!(env.templates.is_empty());
+
+        assert!(env
+            .check("{{ define 'ok' }}<p>{{ .a }}</p>{{ end }}")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_render_compiled() {
+        let tmpl = std::fs::read_to_string("1.html").unwrap();
+        let mut env = Environment::new();
+        env.parse(&tmpl).unwrap();
+        let bytes = env.compile().unwrap();
+
+        let mut compiled = Environment::new();
+        compiled.load_compiled(&bytes).unwrap();
+        let ctx = serde_json::json!({ "name": "lommix" });
+        for name in ["foo", "bar", "foobar"] {
+            assert_eq!(
+                compiled.render(name, &ctx).unwrap(),
+                env.render(name, &ctx).unwrap()
+            );
+        }
+        assert!(Environment::new().load_compiled(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let tmpl = "{{ define 'a' extends 'missing' }}{{ end }}\n\
+                    {{ define 'b' }}{{ if .x }}{{ import 'nope' }}{{ end }}{{ import 'a' }}{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+        let spans = |errors: Vec<TemplusError>| {
+            errors
+                .iter()
+                .map(|err| err.span().map(|span| (span.line(), span.column())))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans(env.validate("a")), vec![Some((1, 4))]);
+        assert_eq!(spans(env.validate("b")), vec![Some((2, 31))]);
+        assert_eq!(spans(env.validate("c")), vec![None]);
+    }
+
+    #[test]
+    fn test_render_serialize_context() {
+        #[derive(Serialize)]
+        struct Item {
+            name: &'static str,
+            price: u32,
+        }
+        #[derive(Serialize)]
+        struct Page {
+            user: Option<Ctx>,
+            items: Vec<Item>,
+        }
+
+        let tmpl = "{{ define 'test' }}{{ .user.name }}:{{ if .user.admin }}admin{{ end }}\
+                    {{ range .items }} {{ .name }}={{ .price * 2 }}{{ end }} {{ .items.1.name }}\
+                    {{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let page = Page {
+            user: Some(Ctx {
+                admin: true,
+                name: "lommix".to_string(),
+                num: 10,
+            }),
+            items: vec![
+                Item {
+                    name: "a",
+                    price: 1,
+                },
+                Item {
+                    name: "b",
+                    price: 5,
+                },
+            ],
+        };
+        let expected = "lommix:admin a=2 b=10 b";
+        assert_eq!(env.render("test", &page).unwrap(), expected);
+        assert_eq!(
+            env.render("test", &serde_json::to_value(&page).unwrap())
+                .unwrap(),
+            expected
+        );
+        assert!(env
+            .render(
+                "test",
+                &Page {
+                    user: None,
+                    items: vec![]
+                }
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_render_escaping() {
+        #[derive(Serialize)]
+        struct Profile {
+            name: &'static str,
+            bio: SafeHtml,
+            links: Vec<SafeHtml>,
+        }
+
+        let tmpl = "{{ define 'card' }}<b>{{ .name }}</b>{{ end }}\
+                    {{ define 'test' }}{{ .name }}|{{ .bio }}|{{ .links.0 }}|{{ .name | safe }}|\
+                    {{ .name + '!' }}|{{ .bio + '!' }}|{{ import 'card' }}{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+
+        let profile = Profile {
+            name: "<a & 'b'>",
+            bio: SafeHtml::from("<p>hi</p>"),
+            links: vec![SafeHtml::from("<a href=\"/\">home</a>")],
+        };
+        assert_eq!(
+            env.render("test", &profile).unwrap(),
+            "&lt;a &amp; &#39;b&#39;&gt;|<p>hi</p>|<a href=\"/\">home</a>|<a & 'b'>|\
+             &lt;a &amp; &#39;b&#39;&gt;!|&lt;p&gt;hi&lt;/p&gt;!|<b>&lt;a &amp; &#39;b&#39;&gt;</b>"
+        );
+
+        env.set_auto_escape(false);
+        assert!(env
+            .render("test", &profile)
+            .unwrap()
+            .starts_with("<a & 'b'>|"));
+    }
+
+    #[test]
+    fn test_render_safe_html_compare() {
+        let mut env = Environment::new();
+        env.parse(
+            "{{ define 'a' }}{{ if .bio == '<p>hi</p>' }}eq{{ end }}\
+             {{ if '<p>hi</p>' == .bio }}eq{{ end }}{{ if .bio != 'x' }}neq{{ end }}{{ end }}",
+        )
+        .unwrap();
+        let ctx = crate::context! { bio => SafeHtml::from("<p>hi</p>") }.unwrap();
+        assert_eq!(env.render("a", &ctx).unwrap(), "eqeqneq");
+    }
+
+    #[test]
+    fn test_globals() {
+        let tmpl = "{{ define 'nav' }}<a href='{{ .base }}/'>{{ .site }}</a>{{ end }}\
+                    {{ define 'test' }}{{ .site }}:{{ range .items }}{{ .name }}@{{ .site }} {{ end }}\
+                    {{ import 'nav' }}{{ end }}";
+        let mut env = Environment::new();
+        env.parse(tmpl).unwrap();
+        env.add_global("site", "templus").unwrap();
+        env.add_global("base", &serde_json::json!("/docs")).unwrap();
+
+        let ctx =
+            serde_json::json!({ "items": [{ "name": "a" }, { "name": "b", "site": "other" }] });
+        assert_eq!(
+            env.render("test", &ctx).unwrap(),
+            "templus:a@templus b@other <a href='/docs/'>templus</a>"
+        );
+
+        let ctx = serde_json::json!({ "site": "mine", "items": [] });
+        assert!(env.render("test", &ctx).unwrap().starts_with("mine:"));
+    }
+
+    #[test]
+    fn test_render_cache() {
+        let mut env = Environment::new();
+        env.parse("{{ define 'a' }}{{ .n }}{{ end }}{{ define 'b' }}{{ import 'a' }}!{{ end }}")
+            .unwrap();
+        env.enable_render_cache(10, 1024);
+        let cached = |env: &Environment| env.cache.as_ref().unwrap().lock().unwrap().len();
+
+        let ctx = serde_json::json!({ "n": 1 });
+        assert_eq!(env.render("b", &ctx).unwrap(), "1!");
+        assert_eq!(env.render("b", &ctx).unwrap(), "1!");
+        assert_eq!(
+            env.render("b", &serde_json::json!({ "n": 2 })).unwrap(),
+            "2!"
+        );
+        assert_eq!(env.render("a", &ctx).unwrap(), "1");
+        assert_eq!(cached(&env), 3);
+
+        env.invalidate("b");
+        assert_eq!(cached(&env), 1);
+        assert!(env.render("missing", &ctx).is_err());
+        assert_eq!(cached(&env), 1);
+
+        env.parse("{{ define 'a' }}<{{ .n }}>{{ end }}").unwrap();
+        assert_eq!(cached(&env), 0);
+        assert_eq!(env.render("b", &ctx).unwrap(), "<1>!");
+        env.add_global("n", &5).unwrap();
+        assert_eq!(cached(&env), 0);
+
+        env.disable_render_cache();
+        assert_eq!(env.render("b", &serde_json::json!({})).unwrap(), "<5>!");
+    }
+
+    #[test]
+    fn test_flatten() {
+        let sources = [
+            std::fs::read_to_string("1.html").unwrap(),
+            std::fs::read_to_string("2.html").unwrap(),
+        ];
+        let mut env = Environment::new();
+        for source in &sources {
+            env.parse(source).unwrap();
+        }
+        env.parse(
+            "{{ define 'mid' extends 'base' }}{{ block 'meta' }}mid{{ end }}{{ block 'js' }}mid{{ end }}{{ end }}\
+             {{ define 'leaf' extends 'mid' }}{{ block 'js' }}{{ if .admin }}leaf{{ end }}{{ end }}{{ end }}",
+        )
+        .unwrap();
+
+        let ctx = serde_json::json!({ "name": "lommix", "admin": true });
+        let walked = env
+            .templates
+            .keys()
+            .map(|name| (name.clone(), env.render(name, &ctx).unwrap()))
+            .collect::<HashMap<_, _>>();
+        env.flatten();
+        for (name, out) in &walked {
+            assert_eq!(&env.render(name, &ctx).unwrap(), out);
+        }
+        let leaf = &walked["leaf"];
+        assert!(leaf.contains("mid") && leaf.contains("leaf") && !leaf.contains("test.js"));
+
+        // parsing again falls back to the tree walk until flattened again
+        env.parse("{{ define 'foobar' }}new{{ end }}").unwrap();
+        assert!(env.render("base", &ctx).unwrap().contains("new"));
+    }
+
+    #[test]
+    fn test_flatten_errors() {
+        let mut env = Environment::new();
+        env.parse(
+            "{{ define 'a' extends 'b' }}{{ end }}{{ define 'b' }}{{ import 'a' }}{{ end }}\
+             {{ define 'c' extends 'missing' }}{{ end }}\
+             {{ define 'd' }}{{ if .show }}{{ import 'missing' }}{{ end }}ok{{ end }}\
+             {{ define 'e' }}e{{ end }}",
+        )
+        .unwrap();
+        env.flatten();
+
+        // broken links fall back to the tree walk, which fails only once they are reached
+        for name in ["a", "b", "c", "d"] {
+            assert!(!env.programs.contains_key(name), "{}", name);
+        }
+        assert!(env.programs.contains_key("e"));
+        let ctx = serde_json::json!({ "show": false });
+        assert!(env.render("a", &ctx).is_err());
+        assert!(env.render("c", &ctx).is_err());
+        assert_eq!(env.render("d", &ctx).unwrap(), "ok");
+        assert!(env
+            .render("d", &serde_json::json!({ "show": true }))
+            .is_err());
+    }
+
+    #[test]
+    fn test_block_override_nesting_itself() {
+        let mut env = Environment::new();
+        env.parse(
+            "{{ define 'base' }}[{{ block 'c' }}base{{ end }}]{{ end }}\
+             {{ define 'page' extends 'base' }}{{ block 'c' }}a{{ block 'c' }}b{{ end }}{{ end }}{{ end }}",
+        )
+        .unwrap();
+        let ctx = serde_json::json!({});
+        assert_eq!(env.render("page", &ctx).unwrap(), "[ab]");
+        env.flatten();
+        assert!(env.programs.contains_key("page"));
+        assert_eq!(env.render("page", &ctx).unwrap(), "[ab]");
+    }
+
+    #[test]
+    fn test_flatten_matches_tree_walk() {
+        let source = "{{ define 'loops' }}\
+            {{ range .users }}[{{ .name }}{{ range .tags }}<{{ .tag }}>{{ end }}\
+            {{ if .admin }}!{{ else }}?{{ end }}{{ range 2 }}{{ .name }}{{ end }}]{{ end }}\
+            {{ range .none }}x{{ end }}{{ range 0 }}x{{ end }}{{ .site }} {{ .n * 2 }} {{ .html | safe }}\
+            {{ end }}\
+            {{ define 'missing' }}{{ range .nope }}x{{ end }}{{ end }}\
+            {{ define 'not_array' }}a{{ range .n }}x{{ end }}{{ end }}\
+            {{ define 'literal' }}{{ if .none }}{{ range 'x' }}{{ end }}{{ end }}{{ range 'x' }}{{ end }}{{ end }}";
+        let ctx = serde_json::json!({
+            "users": [
+                { "name": "a", "admin": true, "tags": [{ "tag": "x" }, { "tag": "y" }] },
+                { "name": "<b>", "admin": false, "tags": [] },
+            ],
+            "none": [],
+            "n": 21,
+            "html": "<i>",
+        });
+
+        for undefined in [UndefinedBehavior::Strict, UndefinedBehavior::Lenient] {
+            let mut walk = Environment::new();
+            walk.set_undefined_behavior(undefined);
+            walk.add_global("site", "templus").unwrap();
+            walk.parse(source).unwrap();
+            let mut flat = Environment::new();
+            flat.set_undefined_behavior(undefined);
+            flat.add_global("site", "templus").unwrap();
+            flat.parse(source).unwrap();
+            flat.flatten();
+
+            for name in ["loops", "missing", "not_array", "literal"] {
+                let expected = walk.render(name, &ctx);
+                let got = flat.render(name, &ctx);
+                match (expected, got) {
+                    (Ok(expected), Ok(got)) => assert_eq!(got, expected, "{}", name),
+                    (Err(expected), Err(got)) => {
+                        assert_eq!(got.to_string(), expected.to_string(), "{}", name)
+                    }
+                    (expected, got) => panic!("{}: {:?} != {:?}", name, got, expected),
+                }
+            }
+            assert_eq!(
+                flat.render("loops", &ctx).unwrap(),
+                "[a<x><y>!aa][&lt;b&gt;?&lt;b&gt;&lt;b&gt;]templus 42 <i>"
+            );
+            // a value context is borrowed by the ranges instead of built up per var
+            let value = Value::from(ctx.clone());
+            assert_eq!(
+                flat.render_with("loops", &value).unwrap(),
+                walk.render_with("loops", &value).unwrap()
+            );
+        }
+    }
 }
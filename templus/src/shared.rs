@@ -0,0 +1,155 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
+use serde::Serialize;
+
+use crate::{compiler::error::TemplusError, renderer::Environment};
+
+self_cell::self_cell!(
+    struct OwnedEnvironment {
+        owner: Vec<String>,
+        #[covariant]
+        dependent: Environment,
+    }
+);
+
+/// An environment together with the template sources it borrows from,
+/// so it can be moved between threads and kept around without a lifetime.
+pub struct Templates(OwnedEnvironment);
+
+impl Templates {
+    /// parses every source into a fresh environment, `configure` runs first
+    /// to set syntax, globals and the like
+    pub fn new(
+        sources: Vec<String>,
+        configure: impl FnOnce(&mut Environment<'_>) -> Result<(), TemplusError>,
+    ) -> Result<Self, TemplusError> {
+        OwnedEnvironment::try_new(sources, |sources| {
+            let mut env = Environment::new();
+            configure(&mut env)?;
+            for source in sources {
+                env.parse(source)?;
+            }
+            // the sources never change again, resolve inheritance once up front
+            env.flatten();
+            Ok(env)
+        })
+        .map(Templates)
+    }
+
+    pub fn environment(&self) -> &Environment<'_> {
+        self.0.borrow_dependent()
+    }
+
+    pub fn render<T: Serialize + ?Sized>(
+        &self,
+        name: &str,
+        ctx: &T,
+    ) -> Result<String, TemplusError> {
+        self.environment().render(name, ctx)
+    }
+}
+
+/// A handle to share templates between threads that can be reloaded while
+/// renders are running. Renders keep the templates they started with, a reload
+/// only waits for other threads to grab or swap the current `Arc`.
+pub struct SharedEnvironment {
+    current: RwLock<Arc<Templates>>,
+}
+
+impl SharedEnvironment {
+    pub fn new(templates: Templates) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(templates)),
+        }
+    }
+
+    /// the templates in use right now, unaffected by later reloads
+    pub fn load(&self) -> Arc<Templates> {
+        // an `Arc` swap cannot leave the lock in a broken state, poisoning is ignored
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn render<T: Serialize + ?Sized>(
+        &self,
+        name: &str,
+        ctx: &T,
+    ) -> Result<String, TemplusError> {
+        self.load().render(name, ctx)
+    }
+
+    /// swaps in new templates for every render started from now on
+    pub fn reload(&self, templates: Templates) {
+        let templates = Arc::new(templates);
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = templates;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn environment_is_send_sync() {
+        assert_send_sync::<Environment<'static>>();
+        assert_send_sync::<Templates>();
+        assert_send_sync::<SharedEnvironment>();
+    }
+
+    fn templates(version: u32) -> Templates {
+        let source = format!(
+            "{{{{ define 'page' }}}}v{}:{{{{ .name }}}}{{{{ end }}}}",
+            version
+        );
+        Templates::new(vec![source], |env| env.add_global("name", "lommix")).unwrap()
+    }
+
+    #[test]
+    fn renders_while_reloading() {
+        let shared = Arc::new(SharedEnvironment::new(templates(0)));
+        let pinned = shared.load();
+
+        let renders = (0..4)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        let out = shared.render("page", &()).unwrap();
+                        assert!(out.starts_with('v') && out.ends_with(":lommix"), "{}", out);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for version in 1..=20 {
+            shared.reload(templates(version));
+        }
+        for render in renders {
+            render.join().unwrap();
+        }
+
+        assert_eq!(pinned.render("page", &()).unwrap(), "v0:lommix");
+        assert_eq!(shared.render("page", &()).unwrap(), "v20:lommix");
+    }
+
+    #[test]
+    fn keeps_templates_with_missing_imports() {
+        let source = "{{ define 'a' }}{{ if .x }}{{ import 'missing' }}{{ end }}a{{ end }}";
+        let templates = Templates::new(vec![source.to_owned()], |_| Ok(())).unwrap();
+        assert_eq!(
+            templates
+                .render("a", &crate::context! { x => false }.unwrap())
+                .unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let result = Templates::new(vec!["{{ define 'a' }}{{ if }}".to_owned()], |_| Ok(()));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Runtime support for templates compiled to Rust code by `templus_macros::include_templates!`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    compiler::{
+        error::TemplusError,
+        lexer::Span,
+        parser::{self, ArithOp, IfExpr, Op},
+    },
+    renderer::{display_value, escape_html},
+    value::{Number, SafeHtml, Value},
+};
+
+/// A context struct with a template compiled for it.
+pub trait Template {
+    /// fails on arithmetic the json renderer rejects too, like division by zero
+    fn render_into(&self, out: &mut String) -> Result<(), TemplusError>;
+
+    fn render(&self) -> Result<String, TemplusError> {
+        let mut out = String::new();
+        self.render_into(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// `{{ .a + .b }}` on typed values, follows the same rules as the json renderer:
+/// ints and floats mix, `+` with a string concatenates, overflow and division by zero fail.
+pub fn arith<L, R>(left: &L, op: ArithOp, right: &R, span: Span) -> Result<Value, TemplusError>
+where
+    L: Operand + ?Sized,
+    R: Operand + ?Sized,
+{
+    op.apply(&left.operand(), &right.operand(), span)
+}
+
+/// `{{ -.value }}` on typed values
+pub fn negate<T: Operand + ?Sized>(value: &T, span: Span) -> Result<Value, TemplusError> {
+    parser::negate(&value.operand(), span)
+}
+
+/// `==`, `<` and friends on typed values, ints and floats compare by value
+pub fn compare<L, R>(left: &L, op: Op, right: &R, span: Span) -> Result<bool, TemplusError>
+where
+    L: Operand + ?Sized,
+    R: Operand + ?Sized,
+{
+    IfExpr::compare(&left.operand(), &op, &right.operand(), span)
+}
+
+/// A typed value as arithmetic and comparisons see it.
+pub trait Operand {
+    fn operand(&self) -> Value;
+}
+
+impl Operand for str {
+    fn operand(&self) -> Value {
+        Value::String(self.to_owned())
+    }
+}
+
+impl Operand for String {
+    fn operand(&self) -> Value {
+        Value::String(self.clone())
+    }
+}
+
+impl Operand for char {
+    fn operand(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl Operand for SafeHtml {
+    fn operand(&self) -> Value {
+        Value::SafeHtml(self.0.clone())
+    }
+}
+
+impl Operand for bool {
+    fn operand(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl Operand for Value {
+    fn operand(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl<T: Operand> Operand for Option<T> {
+    fn operand(&self) -> Value {
+        self.as_ref().map_or(Value::Null, T::operand)
+    }
+}
+
+impl<T: Operand + ?Sized> Operand for &T {
+    fn operand(&self) -> Value {
+        (**self).operand()
+    }
+}
+
+macro_rules! operand_numbers {
+    ($($ty:ty),*) => {
+        $(
+            impl Operand for $ty {
+                // ints too big for a json number become floats, like number literals
+                fn operand(&self) -> Value {
+                    i64::try_from(*self)
+                        .ok()
+                        .map(Number::from)
+                        .or_else(|| u64::try_from(*self).ok().map(Number::from))
+                        .map_or_else(|| Value::from(*self as f64), Value::Number)
+                }
+            }
+        )*
+    };
+}
+
+operand_numbers!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+impl Operand for f32 {
+    fn operand(&self) -> Value {
+        Value::from(*self as f64)
+    }
+}
+
+impl Operand for f64 {
+    fn operand(&self) -> Value {
+        Value::from(*self)
+    }
+}
+
+/// `{{ if .value }}` on typed values, follows the same rules as the json renderer.
+pub trait Truthy {
+    fn truthy(&self) -> bool;
+}
+
+impl Truthy for bool {
+    fn truthy(&self) -> bool {
+        *self
+    }
+}
+
+impl Truthy for str {
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl Truthy for String {
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> Truthy for [T] {
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> Truthy for Vec<T> {
+    fn truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T: Truthy> Truthy for Option<T> {
+    fn truthy(&self) -> bool {
+        self.as_ref().is_some_and(T::truthy)
+    }
+}
+
+impl Truthy for Value {
+    fn truthy(&self) -> bool {
+        parser::is_truthy(self)
+    }
+}
+
+impl<K, V> Truthy for HashMap<K, V> {
+    fn truthy(&self) -> bool {
+        true
+    }
+}
+
+impl<K, V> Truthy for BTreeMap<K, V> {
+    fn truthy(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Truthy + ?Sized> Truthy for &T {
+    fn truthy(&self) -> bool {
+        (**self).truthy()
+    }
+}
+
+macro_rules! truthy_numbers {
+    ($($ty:ty),*) => {
+        $(
+            impl Truthy for $ty {
+                fn truthy(&self) -> bool {
+                    *self != 0 as $ty
+                }
+            }
+        )*
+    };
+}
+
+truthy_numbers!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+/// `{{ .value }}` on typed values, text is html escaped unless `escape` is off
+/// for `| safe` or the value is `SafeHtml`.
+pub trait Output {
+    fn write_output(&self, out: &mut String, escape: bool);
+}
+
+impl Output for str {
+    fn write_output(&self, out: &mut String, escape: bool) {
+        match escape {
+            true => escape_html(out, self),
+            false => out.push_str(self),
+        }
+    }
+}
+
+impl Output for String {
+    fn write_output(&self, out: &mut String, escape: bool) {
+        self.as_str().write_output(out, escape)
+    }
+}
+
+impl Output for char {
+    fn write_output(&self, out: &mut String, escape: bool) {
+        self.encode_utf8(&mut [0; 4]).write_output(out, escape)
+    }
+}
+
+impl Output for SafeHtml {
+    fn write_output(&self, out: &mut String, _: bool) {
+        out.push_str(&self.0)
+    }
+}
+
+impl Output for bool {
+    fn write_output(&self, out: &mut String, _: bool) {
+        out.push_str(if *self { "true" } else { "false" })
+    }
+}
+
+/// results of arithmetic, arrays and objects write nothing
+impl Output for Value {
+    fn write_output(&self, out: &mut String, escape: bool) {
+        match (self, display_value(self)) {
+            (Value::SafeHtml(html), _) => out.push_str(html),
+            (_, Some(text)) => text.write_output(out, escape),
+            (_, None) => {}
+        }
+    }
+}
+
+impl<T: Output> Output for Option<T> {
+    fn write_output(&self, out: &mut String, escape: bool) {
+        if let Some(value) = self {
+            value.write_output(out, escape)
+        }
+    }
+}
+
+impl<T: Output + ?Sized> Output for &T {
+    fn write_output(&self, out: &mut String, escape: bool) {
+        (**self).write_output(out, escape)
+    }
+}
+
+macro_rules! output_numbers {
+    ($fmt:literal: $($ty:ty),*) => {
+        $(
+            impl Output for $ty {
+                fn write_output(&self, out: &mut String, _: bool) {
+                    let _ = std::fmt::Write::write_fmt(out, format_args!($fmt, self));
+                }
+            }
+        )*
+    };
+}
+
+output_numbers!("{}": i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+// floats print like in the json renderer, `5.0` rather than `5`
+output_numbers!("{:?}": f32, f64);
+
+/// `in` and `contains` on typed values: elements of lists, keys of maps, substrings of strings.
+pub trait Contains<T: ?Sized> {
+    fn contains_value(&self, item: &T) -> bool;
+}
+
+impl<T: PartialEq<U>, U> Contains<U> for [T] {
+    fn contains_value(&self, item: &U) -> bool {
+        self.iter().any(|element| element == item)
+    }
+}
+
+impl<T: PartialEq<U>, U> Contains<U> for Vec<T> {
+    fn contains_value(&self, item: &U) -> bool {
+        self.as_slice().contains_value(item)
+    }
+}
+
+impl<U: AsRef<str> + ?Sized> Contains<U> for str {
+    fn contains_value(&self, item: &U) -> bool {
+        self.contains(item.as_ref())
+    }
+}
+
+impl<U: AsRef<str> + ?Sized> Contains<U> for String {
+    fn contains_value(&self, item: &U) -> bool {
+        self.as_str().contains_value(item)
+    }
+}
+
+impl<V, U: AsRef<str> + ?Sized> Contains<U> for HashMap<String, V> {
+    fn contains_value(&self, item: &U) -> bool {
+        self.contains_key(item.as_ref())
+    }
+}
+
+impl<V, U: AsRef<str> + ?Sized> Contains<U> for BTreeMap<String, V> {
+    fn contains_value(&self, item: &U) -> bool {
+        self.contains_key(item.as_ref())
+    }
+}
+
+impl<T: Contains<U> + ?Sized, U: ?Sized> Contains<U> for &T {
+    fn contains_value(&self, item: &U) -> bool {
+        (**self).contains_value(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truthy_values() {
+        assert!(true.truthy());
+        assert!(!0.truthy());
+        assert!(1.5.truthy());
+        assert!(!"".truthy());
+        assert!(!Vec::<u8>::new().truthy());
+        assert!(!None::<bool>.truthy());
+        assert!(!Some(false).truthy());
+        assert!(Some("a").truthy());
+    }
+
+    #[test]
+    fn output_values() {
+        let mut out = String::new();
+        "<b>".write_output(&mut out, true);
+        "<b>".write_output(&mut out, false);
+        SafeHtml::from("<i>").write_output(&mut out, true);
+        Some(2.0).write_output(&mut out, true);
+        None::<i32>.write_output(&mut out, true);
+        assert_eq!(out, "&lt;b&gt;<b><i>2.0");
+    }
+
+    #[test]
+    fn contains_values() {
+        assert!(vec!["a".to_owned()].contains_value(&"a"));
+        assert!(!vec![1, 2].contains_value(&3));
+        assert!("lommix".contains_value("om"));
+        assert!(String::from("lommix").contains_value(&"mix"));
+        let map = HashMap::from([("key".to_owned(), 1)]);
+        assert!(map.contains_value("key"));
+    }
+}
@@ -0,0 +1,841 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use serde::{
+    de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
+    ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer},
+};
+
+/// The values templates work with. Contexts from serde_json, toml or yaml are converted
+/// into this, `Deserialize` turns any other self describing format into one as well.
+#[derive(Debug, Clone, Default, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    /// trusted markup, rendered without escaping
+    SafeHtml(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+/// newtype name `SafeHtml` serializes with, lets `to_value` tell it apart from strings
+pub(crate) const SAFE_HTML: &str = "$templus::SafeHtml";
+
+/// Markup that is rendered as is instead of being escaped, like rendered markdown
+/// or a sanitized user bio. Serializes as a plain string in any other format.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SafeHtml(pub String);
+
+impl fmt::Display for SafeHtml {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for SafeHtml {
+    fn from(html: String) -> Self {
+        SafeHtml(html)
+    }
+}
+
+impl From<&str> for SafeHtml {
+    fn from(html: &str) -> Self {
+        SafeHtml(html.to_owned())
+    }
+}
+
+impl Serialize for SafeHtml {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(SAFE_HTML, &self.0)
+    }
+}
+
+/// An int or a finite float. Ints stay exact, including u64 values above i64::MAX.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Number(N);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum N {
+    PosInt(u64),
+    NegInt(i64),
+    Float(f64),
+}
+
+impl Number {
+    /// `None` for NaN and infinities
+    pub fn from_f64(num: f64) -> Option<Self> {
+        num.is_finite().then_some(Number(N::Float(num)))
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.0 {
+            N::PosInt(num) => i64::try_from(num).ok(),
+            N::NegInt(num) => Some(num),
+            N::Float(_) => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.0 {
+            N::PosInt(num) => Some(num),
+            N::NegInt(_) | N::Float(_) => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.0 {
+            N::PosInt(num) => Some(num as f64),
+            N::NegInt(num) => Some(num as f64),
+            N::Float(num) => Some(num),
+        }
+    }
+
+    pub fn is_f64(&self) -> bool {
+        matches!(self.0, N::Float(_))
+    }
+}
+
+impl From<i64> for Number {
+    fn from(num: i64) -> Self {
+        match u64::try_from(num) {
+            Ok(num) => Number(N::PosInt(num)),
+            Err(_) => Number(N::NegInt(num)),
+        }
+    }
+}
+
+impl From<u64> for Number {
+    fn from(num: u64) -> Self {
+        Number(N::PosInt(num))
+    }
+}
+
+/// ints as they are, floats in their shortest form that still reads back the same
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            N::PosInt(num) => write!(f, "{}", num),
+            N::NegInt(num) => write!(f, "{}", num),
+            N::Float(num) => write!(f, "{:?}", num),
+        }
+    }
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+}
+
+macro_rules! from_int {
+    ($($ty:ty => $via:ty),*) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(num: $ty) -> Self {
+                    Value::Number(Number::from(<$via>::from(num)))
+                }
+            }
+        )*
+    };
+}
+
+from_int!(i8 => i64, i16 => i64, i32 => i64, i64 => i64, u8 => u64, u16 => u64, u32 => u64, u64 => u64);
+
+/// non finite floats become null, like in serde_json
+impl From<f64> for Value {
+    fn from(num: f64) -> Self {
+        Number::from_f64(num).map_or(Value::Null, Value::Number)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(bool: bool) -> Self {
+        Value::Bool(bool)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        Value::String(string.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Self {
+        Value::String(string)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(bool) => Value::Bool(bool),
+            serde_json::Value::Number(num) => match (num.as_u64(), num.as_i64()) {
+                (Some(num), _) => Value::from(num),
+                (_, Some(num)) => Value::from(num),
+                _ => num.as_f64().map_or(Value::Null, Value::from),
+            },
+            serde_json::Value::String(string) => Value::String(string),
+            serde_json::Value::Array(items) => Value::from(items),
+            serde_json::Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// datetimes are kept as their toml string form
+#[cfg(feature = "toml")]
+impl From<toml::Value> for Value {
+    fn from(value: toml::Value) -> Self {
+        match value {
+            toml::Value::String(string) => Value::String(string),
+            toml::Value::Integer(num) => Value::from(num),
+            toml::Value::Float(num) => Value::from(num),
+            toml::Value::Boolean(bool) => Value::Bool(bool),
+            toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+            toml::Value::Array(items) => Value::from(items),
+            toml::Value::Table(table) => Value::Object(
+                table
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// scalar mapping keys are turned into strings, mappings and sequences as keys are dropped
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Value> for Value {
+    fn from(value: serde_yaml::Value) -> Self {
+        use serde_yaml::Value as Yaml;
+        match value {
+            Yaml::Null => Value::Null,
+            Yaml::Bool(bool) => Value::Bool(bool),
+            Yaml::Number(num) => match (num.as_u64(), num.as_i64()) {
+                (Some(num), _) => Value::from(num),
+                (_, Some(num)) => Value::from(num),
+                _ => num.as_f64().map_or(Value::Null, Value::from),
+            },
+            Yaml::String(string) => Value::String(string),
+            Yaml::Sequence(items) => Value::from(items),
+            Yaml::Mapping(map) => Value::Object(
+                map.into_iter()
+                    .filter_map(|(key, value)| {
+                        let key = match Value::from(key) {
+                            Value::String(key) => key,
+                            Value::Bool(bool) => bool.to_string(),
+                            Value::Number(num) => num.to_string(),
+                            _ => return None,
+                        };
+                        Some((key, value.into()))
+                    })
+                    .collect(),
+            ),
+            Yaml::Tagged(tagged) => Value::from(tagged.value),
+        }
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(bool) => serializer.serialize_bool(*bool),
+            Value::Number(num) => num.serialize(serializer),
+            Value::String(string) => serializer.serialize_str(string),
+            Value::SafeHtml(html) => serializer.serialize_newtype_struct(SAFE_HTML, html),
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Object(map) => {
+                let mut out = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map {
+                    out.serialize_entry(key, value)?;
+                }
+                out.end()
+            }
+        }
+    }
+}
+
+/// floats hash by their bits, with `-0.0` hashed like `0.0` as they are equal
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0 {
+            N::PosInt(num) => (0u8, num).hash(state),
+            N::NegInt(num) => (1u8, num).hash(state),
+            N::Float(0.0) => (2u8, 0u64).hash(state),
+            N::Float(num) => (2u8, num.to_bits()).hash(state),
+        }
+    }
+}
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            N::PosInt(num) => serializer.serialize_u64(num),
+            N::NegInt(num) => serializer.serialize_i64(num),
+            N::Float(num) => serializer.serialize_f64(num),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any templus value")
+    }
+
+    fn visit_bool<E>(self, bool: bool) -> Result<Value, E> {
+        Ok(Value::Bool(bool))
+    }
+
+    fn visit_i64<E>(self, num: i64) -> Result<Value, E> {
+        Ok(Value::from(num))
+    }
+
+    fn visit_u64<E>(self, num: u64) -> Result<Value, E> {
+        Ok(Value::from(num))
+    }
+
+    fn visit_f64<E>(self, num: f64) -> Result<Value, E> {
+        Ok(Value::from(num))
+    }
+
+    fn visit_str<E>(self, string: &str) -> Result<Value, E> {
+        Ok(Value::from(string))
+    }
+
+    fn visit_string<E>(self, string: String) -> Result<Value, E> {
+        Ok(Value::String(string))
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut out = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<String, Value>()? {
+            out.insert(key, value);
+        }
+        Ok(Value::Object(out))
+    }
+}
+
+#[doc(hidden)]
+pub fn __context_insert<T: Serialize + ?Sized>(
+    map: &mut BTreeMap<String, Value>,
+    key: &str,
+    value: &T,
+) -> Result<(), Error> {
+    map.insert(key.to_owned(), to_value(value)?);
+    Ok(())
+}
+
+#[doc(hidden)]
+pub fn __context_nested(
+    map: &mut BTreeMap<String, Value>,
+    key: &str,
+    value: Result<Value, Error>,
+) -> Result<(), Error> {
+    map.insert(key.to_owned(), value?);
+    Ok(())
+}
+
+/// `..base` in `context!`, only things that serialize to an object can be spread
+#[doc(hidden)]
+pub fn __context_spread<T: Serialize + ?Sized>(
+    map: &mut BTreeMap<String, Value>,
+    base: &T,
+) -> Result<(), Error> {
+    match to_value(base)? {
+        Value::Object(fields) => {
+            map.extend(fields);
+            Ok(())
+        }
+        _ => Err(Error(
+            "only objects can be spread into a context".to_owned(),
+        )),
+    }
+}
+
+/// Converts any `Serialize` type into a `Value`, `SafeHtml` anywhere inside stays safe.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, Error> {
+    value.serialize(ValueSerializer)
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// builds values in the same shape as serde_json does
+pub(crate) struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeVariant<SerializeObject>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value, Error> {
+        match (i64::try_from(v), u64::try_from(v)) {
+            (Ok(v), _) => Ok(Value::from(v)),
+            (_, Ok(v)) => Ok(Value::from(v)),
+            _ => Err(Error("number out of range".to_owned())),
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value, Error> {
+        u64::try_from(v)
+            .map(Value::from)
+            .map_err(|_| Error("number out of range".to_owned()))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::from(f64::from(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::Array(
+            v.iter().map(|&byte| Value::from(byte)).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        Ok(Value::from(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        match (name, value.serialize(self)?) {
+            (SAFE_HTML, Value::String(html)) => Ok(Value::SafeHtml(html)),
+            (_, value) => Ok(value),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        Ok(tagged(variant, to_value(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, Error> {
+        Ok(SerializeArray(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<SerializeArray, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<SerializeObject, Error> {
+        Ok(SerializeObject {
+            map: BTreeMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<SerializeObject, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+pub(crate) struct SerializeArray(Vec<Value>);
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+pub(crate) struct SerializeObject {
+    map: BTreeMap<String, Value>,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = Value;
+    type Error = Error;
+
+    // keys become strings like in json, anything that is not a scalar is rejected
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(match to_value(key)? {
+            Value::String(key) | Value::SafeHtml(key) => key,
+            Value::Number(num) => num.to_string(),
+            Value::Bool(bool) => bool.to_string(),
+            _ => return Err(Error("key must be a string".to_owned())),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error("value without a key".to_owned()))?;
+        self.map.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeObject {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.map.insert(key.to_owned(), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(Value::Object(self.map))
+    }
+}
+
+/// enum variants with fields become `{ "Variant": fields }`
+fn tagged(variant: &str, value: Value) -> Value {
+    let mut map = BTreeMap::new();
+    map.insert(variant.to_owned(), value);
+    Value::Object(map)
+}
+
+pub(crate) struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(tagged(self.variant, value))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeObject> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        let value = ser::SerializeStruct::end(self.inner)?;
+        Ok(tagged(self.variant, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn value_from_json() {
+        let json = json!({ "a": [1, -2, 2.5, u64::MAX], "b": null, "c": "x", "d": true });
+        let value = Value::from(json.clone());
+        let Value::Object(map) = &value else {
+            panic!("{:?}", value)
+        };
+        assert_eq!(
+            map["a"],
+            Value::Array(vec![
+                Value::from(1),
+                Value::from(-2),
+                Value::from(2.5),
+                Value::from(u64::MAX)
+            ])
+        );
+        assert_eq!(serde_json::to_value(&value).unwrap(), json);
+        assert_eq!(serde_json::from_value::<Value>(json).unwrap(), value);
+    }
+
+    #[test]
+    fn to_value_keeps_safe_html() {
+        #[derive(serde::Serialize)]
+        enum Kind {
+            Note { html: SafeHtml },
+        }
+
+        let value = to_value(&(
+            SafeHtml::from("<b>"),
+            Kind::Note { html: "<i>".into() },
+            'c',
+        ))
+        .unwrap();
+        let mut note = BTreeMap::new();
+        note.insert("html".to_owned(), Value::SafeHtml("<i>".to_owned()));
+        let mut kind = BTreeMap::new();
+        kind.insert("Note".to_owned(), Value::Object(note));
+        assert_eq!(
+            value,
+            Value::Array(vec![
+                Value::SafeHtml("<b>".to_owned()),
+                Value::Object(kind),
+                Value::from("c")
+            ])
+        );
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            json!(["<b>", { "Note": { "html": "<i>" } }, "c"])
+        );
+    }
+
+    #[test]
+    fn number_display() {
+        assert_eq!(Number::from(-3i64).to_string(), "-3");
+        assert_eq!(Number::from(u64::MAX).to_string(), "18446744073709551615");
+        assert_eq!(Number::from_f64(2.5).unwrap().to_string(), "2.5");
+        assert_eq!(Number::from_f64(5.0).unwrap().to_string(), "5.0");
+        assert!(Number::from_f64(f64::NAN).is_none());
+        assert_eq!(Value::from(f64::INFINITY), Value::Null);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn value_from_toml() {
+        let toml = "name = 'lommix'\nborn = 1979-05-27\n[stats]\nlevel = 3\n";
+        let value = Value::from(toml.parse::<toml::Value>().unwrap());
+        let json = json!({ "name": "lommix", "born": "1979-05-27", "stats": { "level": 3 } });
+        assert_eq!(value, Value::from(json));
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn value_from_yaml() {
+        let yaml = "name: lommix\n1: one\nitems: [1, 2.5, ~]\n";
+        let value = Value::from(serde_yaml::from_str::<serde_yaml::Value>(yaml).unwrap());
+        let json = json!({ "name": "lommix", "1": "one", "items": [1, 2.5, null] });
+        assert_eq!(value, Value::from(json));
+    }
+}